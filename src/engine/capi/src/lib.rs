@@ -1,6 +1,9 @@
 #![allow(clippy::missing_safety_doc)]
 
-pub use kime_engine_core::{Config, InputEngine, InputResult, ModifierState};
+pub use kime_engine_core::{
+    Config, EngineSnapshot, InputEngine, InputPurpose, InputResult, JamoSlot, ModeIndicator,
+    ModifierState, PreeditSegment, PreeditStyle, Theme,
+};
 
 #[repr(C)]
 pub struct XimPreeditFont {
@@ -23,7 +26,45 @@ impl RustStr {
     }
 }
 
-pub const KIME_API_VERSION: usize = 2;
+#[repr(C)]
+pub struct PreeditSegments {
+    ptr: *const PreeditSegment,
+    len: usize,
+}
+
+/// Jamo slots of syllable being composed, empty slot is 0
+#[repr(C)]
+pub struct JamoState {
+    cho: u32,
+    jung: u32,
+    jong: u32,
+    compose_jung: bool,
+    next: JamoSlot,
+}
+
+/// Current page of opened candidate list, len is 0 when list is closed
+#[repr(C)]
+pub struct CandidatePage {
+    len: usize,
+    /// Index of selected candidate in page
+    selected: usize,
+    page: usize,
+    page_count: usize,
+}
+
+#[repr(C)]
+pub struct CandidateItem {
+    value: RustStr,
+    comment: RustStr,
+}
+
+/// Saved engine state
+pub struct Snapshot {
+    snapshot: EngineSnapshot,
+    buf: String,
+}
+
+pub const KIME_API_VERSION: usize = 3;
 
 /// Return API version
 #[no_mangle]
@@ -43,6 +84,112 @@ pub extern "C" fn kime_engine_set_hangul_enable(engine: &mut InputEngine, mode:
     engine.set_hangul_enable(mode);
 }
 
+/// Get hangul enable state
+#[no_mangle]
+pub extern "C" fn kime_engine_is_hangul_enabled(engine: &InputEngine) -> bool {
+    engine.is_hangul_enabled()
+}
+
+/// Set program name used by hotkey condition
+///
+/// # Safety
+///
+/// app must be valid pointer of bytes with length len, invalid utf8 string is ignored
+#[no_mangle]
+pub unsafe extern "C" fn kime_engine_set_app(engine: &mut InputEngine, app: *const u8, len: usize) {
+    if let Ok(app) = std::str::from_utf8(std::slice::from_raw_parts(app, len)) {
+        engine.set_app(app);
+    }
+}
+
+/// Set purpose of focused field
+///
+/// ## Return
+///
+/// input result, has `LANGUAGE_CHANGED` when hangul state is changed by `purpose_modes`
+#[no_mangle]
+pub extern "C" fn kime_engine_set_purpose(
+    engine: &mut InputEngine,
+    config: &Config,
+    purpose: InputPurpose,
+) -> InputResult {
+    engine.set_purpose(purpose, config)
+}
+
+/// Tell engine that frontend can show candidate list by itself
+#[no_mangle]
+pub extern "C" fn kime_engine_set_candidate_support(engine: &mut InputEngine, support: bool) {
+    engine.set_candidate_support(support);
+}
+
+/// Get current page of candidate list
+///
+/// ## Return
+///
+/// page info, only valid when input result has `HAS_CANDIDATE`
+#[no_mangle]
+pub extern "C" fn kime_engine_candidate_page(engine: &InputEngine) -> CandidatePage {
+    match engine.candidates() {
+        Some(list) => CandidatePage {
+            len: list.page_items().len(),
+            selected: list.selected() % kime_engine_core::CANDIDATE_PAGE_SIZE,
+            page: list.page(),
+            page_count: list.page_count(),
+        },
+        None => CandidatePage {
+            len: 0,
+            selected: 0,
+            page: 0,
+            page_count: 0,
+        },
+    }
+}
+
+/// Get candidate in current page
+/// strings only valid until next call of engine
+///
+/// ## Return
+///
+/// empty strings when index is out of page
+#[no_mangle]
+pub extern "C" fn kime_engine_candidate(engine: &InputEngine, index: usize) -> CandidateItem {
+    match engine.candidates().and_then(|list| list.page_item(index)) {
+        Some(candidate) => CandidateItem {
+            value: RustStr::new(&candidate.value),
+            comment: RustStr::new(&candidate.comment),
+        },
+        None => CandidateItem {
+            value: RustStr::new(""),
+            comment: RustStr::new(""),
+        },
+    }
+}
+
+/// Set text before cursor used by reconversion
+///
+/// # Safety
+///
+/// before_cursor must be valid pointer with length len, invalid utf8 is treated as empty
+#[no_mangle]
+pub unsafe extern "C" fn kime_engine_set_surrounding_text(
+    engine: &mut InputEngine,
+    before_cursor: *const u8,
+    len: usize,
+) {
+    let before_cursor = std::slice::from_raw_parts(before_cursor, len);
+    engine.set_surrounding_text(std::str::from_utf8(before_cursor).unwrap_or_default());
+}
+
+/// Get count of characters to delete before cursor
+///
+/// ## Return
+///
+/// count of characters, only valid when input result has `DELETE_SURROUNDING`
+#[no_mangle]
+pub extern "C" fn kime_engine_delete_surrounding(engine: &InputEngine) -> usize {
+    engine.delete_surrounding()
+}
+
 /// Delete engine
 ///
 /// # Safety
@@ -79,6 +226,87 @@ pub extern "C" fn kime_engine_preedit_str(engine: &mut InputEngine) -> RustStr {
     RustStr::new(engine.preedit_str())
 }
 
+/// Get cursor position of preedit string
+///
+/// ## Return
+///
+/// byte offset of cursor in preedit string
+#[no_mangle]
+pub extern "C" fn kime_engine_preedit_cursor(engine: &mut InputEngine) -> usize {
+    engine.preedit_cursor()
+}
+
+/// Get segments of preedit string with style hints
+/// segments only valid until next call of engine
+///
+/// ## Return
+///
+/// segments ordered by offset
+#[no_mangle]
+pub extern "C" fn kime_engine_preedit_segments(engine: &mut InputEngine) -> PreeditSegments {
+    let segments = engine.preedit_segments();
+
+    PreeditSegments {
+        ptr: segments.as_ptr(),
+        len: segments.len(),
+    }
+}
+
+/// Get jamo slots of syllable being composed
+///
+/// ## Return
+///
+/// unicode scalar values of compatibility jamo
+#[no_mangle]
+pub extern "C" fn kime_engine_jamo_state(engine: &InputEngine) -> JamoState {
+    let composition = engine.composition();
+
+    JamoState {
+        cho: composition.cho.map_or(0, u32::from),
+        jung: composition.jung.map_or(0, u32::from),
+        jong: composition.jong.map_or(0, u32::from),
+        compose_jung: composition.compose_jung,
+        next: composition.next_slot(),
+    }
+}
+
+/// Load trailing hangul of text before cursor into preedit
+///
+/// # Safety
+///
+/// before_cursor must be valid pointer with length len
+///
+/// ## Return
+///
+/// count of characters frontend must delete before cursor, 0 when before_cursor is invalid utf8
+#[no_mangle]
+pub unsafe extern "C" fn kime_engine_reconvert(
+    engine: &mut InputEngine,
+    config: &Config,
+    before_cursor: *const u8,
+    len: usize,
+) -> usize {
+    match std::str::from_utf8(std::slice::from_raw_parts(before_cursor, len)) {
+        Ok(before_cursor) => engine.reconvert(before_cursor, config),
+        Err(_) => 0,
+    }
+}
+
+/// Save hangul mode and preedit state of engine
+#[no_mangle]
+pub extern "C" fn kime_engine_snapshot(engine: &InputEngine) -> *mut Snapshot {
+    Box::into_raw(Box::new(Snapshot {
+        snapshot: engine.snapshot(),
+        buf: String::new(),
+    }))
+}
+
+/// Restore engine state saved by `kime_engine_snapshot`
+#[no_mangle]
+pub extern "C" fn kime_engine_restore(engine: &mut InputEngine, snapshot: &Snapshot) {
+    engine.restore(&snapshot.snapshot);
+}
+
 /// Flush commit_str
 #[no_mangle]
 pub extern "C" fn kime_engine_flush(engine: &mut InputEngine) {
@@ -91,6 +319,16 @@ pub extern "C" fn kime_engine_clear_preedit(engine: &mut InputEngine) {
     engine.clear_preedit();
 }
 
+/// Apply focus out commit policy
+///
+/// ## Return
+///
+/// input result, preedit is kept when it doesn't have `NEED_RESET`
+#[no_mangle]
+pub extern "C" fn kime_engine_focus_out(engine: &mut InputEngine, config: &Config) -> InputResult {
+    engine.focus_out(config)
+}
+
 /// Reset preedit state then returm commit char
 #[no_mangle]
 pub extern "C" fn kime_engine_reset(engine: &mut InputEngine) {
@@ -112,6 +350,93 @@ pub extern "C" fn kime_engine_press_key(
     engine.press_key_code(hardware_code, state, config)
 }
 
+/// Get time left until pending hotkey sequence expires
+///
+/// ## Return
+///
+/// milliseconds, `UINT64_MAX` when no sequence is pending
+#[no_mangle]
+pub extern "C" fn kime_engine_sequence_timeout(engine: &InputEngine, config: &Config) -> u64 {
+    // Round up so sequence is already expired when timer of frontend is fired
+    engine.sequence_timeout(config).map_or(u64::MAX, |timeout| {
+        timeout.as_millis() as u64 + u64::from(timeout.subsec_nanos() % 1_000_000 != 0)
+    })
+}
+
+/// Drop pending hotkey sequence when its timeout is passed
+///
+/// ## Return
+///
+/// input result, empty when nothing is expired
+#[no_mangle]
+pub extern "C" fn kime_engine_expire_sequence(
+    engine: &mut InputEngine,
+    config: &Config,
+) -> InputResult {
+    engine.expire_sequence(config)
+}
+
+/// Release key when modifier state
+///
+/// ## Return
+///
+/// input result, empty when nothing happened
+#[no_mangle]
+pub extern "C" fn kime_engine_release_key(
+    engine: &mut InputEngine,
+    config: &Config,
+    hardware_code: u16,
+    state: ModifierState,
+) -> InputResult {
+    engine.release_key_code(hardware_code, state, config)
+}
+
+/// Serialize snapshot
+///
+/// ## Return
+///
+/// valid utf8 string, only valid while snapshot is live
+#[no_mangle]
+pub extern "C" fn kime_snapshot_serialize(snapshot: &mut Snapshot) -> RustStr {
+    snapshot.buf = snapshot.snapshot.to_yaml();
+    RustStr::new(&snapshot.buf)
+}
+
+/// Deserialize snapshot made by `kime_snapshot_serialize`
+///
+/// # Safety
+///
+/// data must be valid pointer with length len
+///
+/// ## Return
+///
+/// null when data is invalid
+#[no_mangle]
+pub unsafe extern "C" fn kime_snapshot_deserialize(data: *const u8, len: usize) -> *mut Snapshot {
+    let data = match std::str::from_utf8(std::slice::from_raw_parts(data, len)) {
+        Ok(data) => data,
+        Err(_) => return std::ptr::null_mut(),
+    };
+
+    match EngineSnapshot::from_yaml(data) {
+        Ok(snapshot) => Box::into_raw(Box::new(Snapshot {
+            snapshot,
+            buf: String::new(),
+        })),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Delete snapshot
+///
+/// # Safety
+///
+/// snapshot must be created by `kime_engine_snapshot` or `kime_snapshot_deserialize` and never call delete more than once
+#[no_mangle]
+pub unsafe extern "C" fn kime_snapshot_delete(snapshot: *mut Snapshot) {
+    drop(Box::from_raw(snapshot));
+}
+
 /// Load config from local file
 #[no_mangle]
 pub extern "C" fn kime_config_load() -> *mut Config {
@@ -145,3 +470,27 @@ pub extern "C" fn kime_config_xim_preedit_font(config: &Config) -> XimPreeditFon
         size,
     }
 }
+
+/// Get xim_preedit_callbacks config
+#[no_mangle]
+pub extern "C" fn kime_config_xim_preedit_callbacks(config: &Config) -> bool {
+    config.xim_preedit_callbacks()
+}
+
+/// Get theme config
+#[no_mangle]
+pub extern "C" fn kime_config_theme(config: &Config) -> Theme {
+    config.theme()
+}
+
+/// Get mode_indicator config
+#[no_mangle]
+pub extern "C" fn kime_config_mode_indicator(config: &Config) -> ModeIndicator {
+    config.mode_indicator()
+}
+
+/// Get ignore_caps_lock config
+#[no_mangle]
+pub extern "C" fn kime_config_ignore_caps_lock(config: &Config) -> bool {
+    config.ignore_caps_lock()
+}
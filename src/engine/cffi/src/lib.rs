@@ -11,9 +11,15 @@ mod ffi {
 extern "C" {}
 
 pub use ffi::{
-    InputResult, InputResult_CONSUMED, InputResult_HAS_PREEDIT, InputResult_LANGUAGE_CHANGED,
-    InputResult_NEED_FLUSH, InputResult_NEED_RESET, ModifierState, ModifierState_ALT,
-    ModifierState_CONTROL, ModifierState_SHIFT, ModifierState_SUPER,
+    CandidatePage, Color, InputPurpose, InputPurpose_Normal, InputPurpose_Password,
+    InputPurpose_Pin, InputPurpose_Terminal, InputResult, InputResult_CONSUMED,
+    InputResult_DELETE_SURROUNDING, InputResult_HAS_CANDIDATE, InputResult_HAS_PREEDIT,
+    InputResult_LANGUAGE_CHANGED, InputResult_NEED_FLUSH, InputResult_NEED_RESET,
+    InputResult_REPLAY_SEQUENCE, InputResult_SEQUENCE_PENDING, JamoSlot, JamoSlot_Choseong,
+    JamoSlot_Jongseong, JamoSlot_Jungseong, JamoState, ModeIndicator, ModifierState,
+    ModifierState_ALT, ModifierState_CAPSLOCK, ModifierState_CONTROL, ModifierState_NUMLOCK,
+    ModifierState_SHIFT, ModifierState_SUPER, PreeditSegment, PreeditStyle, PreeditStyle_Composing,
+    PreeditStyle_Hint, PreeditStyle_SelectedCandidate, PreeditStyle_Word, Theme,
 };
 
 pub fn check_api_version() -> bool {
@@ -39,6 +45,62 @@ impl InputEngine {
         unsafe { ffi::kime_engine_set_hangul_enable(self.engine, mode) };
     }
 
+    pub fn is_hangul_enabled(&self) -> bool {
+        unsafe { ffi::kime_engine_is_hangul_enabled(self.engine) }
+    }
+
+    pub fn set_app(&mut self, app: &str) {
+        unsafe { ffi::kime_engine_set_app(self.engine, app.as_ptr(), app.len()) };
+    }
+
+    /// Set purpose of focused field
+    pub fn set_purpose(&mut self, config: &Config, purpose: InputPurpose) -> InputResult {
+        unsafe { ffi::kime_engine_set_purpose(self.engine, config.config, purpose) }
+    }
+
+    /// Frontend can show candidate list by itself
+    pub fn set_candidate_support(&mut self, support: bool) {
+        unsafe { ffi::kime_engine_set_candidate_support(self.engine, support) };
+    }
+
+    /// Current page of candidate list, len is 0 when list is closed
+    pub fn candidate_page(&self) -> CandidatePage {
+        unsafe { ffi::kime_engine_candidate_page(self.engine) }
+    }
+
+    /// Value and comment of candidate in current page
+    pub fn candidate(&self, index: usize) -> (&str, &str) {
+        unsafe {
+            let c = ffi::kime_engine_candidate(self.engine, index);
+            (
+                core::str::from_utf8_unchecked(core::slice::from_raw_parts(
+                    c.value.ptr,
+                    c.value.len,
+                )),
+                core::str::from_utf8_unchecked(core::slice::from_raw_parts(
+                    c.comment.ptr,
+                    c.comment.len,
+                )),
+            )
+        }
+    }
+
+    /// Set text before cursor used by reconversion
+    pub fn set_surrounding_text(&mut self, before_cursor: &str) {
+        unsafe {
+            ffi::kime_engine_set_surrounding_text(
+                self.engine,
+                before_cursor.as_ptr(),
+                before_cursor.len(),
+            )
+        };
+    }
+
+    /// Count of characters to delete before cursor
+    pub fn delete_surrounding(&self) -> usize {
+        unsafe { ffi::kime_engine_delete_surrounding(self.engine) }
+    }
+
     pub fn press_key(
         &mut self,
         config: &Config,
@@ -48,6 +110,28 @@ impl InputEngine {
         unsafe { ffi::kime_engine_press_key(self.engine, config.config, hardware_code, state) }
     }
 
+    pub fn release_key(
+        &mut self,
+        config: &Config,
+        hardware_code: u16,
+        state: ModifierState,
+    ) -> InputResult {
+        unsafe { ffi::kime_engine_release_key(self.engine, config.config, hardware_code, state) }
+    }
+
+    /// Time left until pending hotkey sequence expires, `None` when no sequence is pending
+    pub fn sequence_timeout(&self, config: &Config) -> Option<core::time::Duration> {
+        match unsafe { ffi::kime_engine_sequence_timeout(self.engine, config.config) } {
+            u64::MAX => None,
+            timeout => Some(core::time::Duration::from_millis(timeout)),
+        }
+    }
+
+    /// Drop pending hotkey sequence when its timeout is passed, kept keys must be replayed
+    pub fn expire_sequence(&mut self, config: &Config) -> InputResult {
+        unsafe { ffi::kime_engine_expire_sequence(self.engine, config.config) }
+    }
+
     pub fn preedit_str(&self) -> &str {
         unsafe {
             let s = ffi::kime_engine_preedit_str(self.engine);
@@ -55,6 +139,47 @@ impl InputEngine {
         }
     }
 
+    /// Byte offset of cursor in `preedit_str`
+    pub fn preedit_cursor(&self) -> usize {
+        unsafe { ffi::kime_engine_preedit_cursor(self.engine) }
+    }
+
+    pub fn preedit_segments(&self) -> &[PreeditSegment] {
+        unsafe {
+            let s = ffi::kime_engine_preedit_segments(self.engine);
+            core::slice::from_raw_parts(s.ptr, s.len)
+        }
+    }
+
+    /// Load trailing hangul of `before_cursor` into preedit
+    ///
+    /// Return count of characters to delete before cursor
+    pub fn reconvert(&mut self, config: &Config, before_cursor: &str) -> usize {
+        unsafe {
+            ffi::kime_engine_reconvert(
+                self.engine,
+                config.config,
+                before_cursor.as_ptr(),
+                before_cursor.len(),
+            )
+        }
+    }
+
+    /// Save hangul mode and preedit state
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            snapshot: unsafe { ffi::kime_engine_snapshot(self.engine) },
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        unsafe { ffi::kime_engine_restore(self.engine, snapshot.snapshot) }
+    }
+
+    pub fn jamo_state(&self) -> JamoState {
+        unsafe { ffi::kime_engine_jamo_state(self.engine) }
+    }
+
     pub fn commit_str(&self) -> &str {
         unsafe {
             let s = ffi::kime_engine_commit_str(self.engine);
@@ -68,6 +193,10 @@ impl InputEngine {
         }
     }
 
+    pub fn focus_out(&mut self, config: &Config) -> InputResult {
+        unsafe { ffi::kime_engine_focus_out(self.engine, config.config) }
+    }
+
     pub fn flush(&mut self) {
         unsafe {
             ffi::kime_engine_flush(self.engine);
@@ -89,6 +218,37 @@ impl Drop for InputEngine {
     }
 }
 
+pub struct Snapshot {
+    snapshot: *mut ffi::Snapshot,
+}
+
+impl Snapshot {
+    pub fn deserialize(data: &str) -> Option<Self> {
+        let snapshot = unsafe { ffi::kime_snapshot_deserialize(data.as_ptr(), data.len()) };
+
+        if snapshot.is_null() {
+            None
+        } else {
+            Some(Self { snapshot })
+        }
+    }
+
+    pub fn serialize(&mut self) -> &str {
+        unsafe {
+            let s = ffi::kime_snapshot_serialize(self.snapshot);
+            core::str::from_utf8_unchecked(core::slice::from_raw_parts(s.ptr, s.len))
+        }
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::kime_snapshot_delete(self.snapshot);
+        }
+    }
+}
+
 pub struct Config {
     config: *mut ffi::Config,
 }
@@ -121,6 +281,22 @@ impl Config {
             )
         }
     }
+
+    pub fn ignore_caps_lock(&self) -> bool {
+        unsafe { ffi::kime_config_ignore_caps_lock(self.config) }
+    }
+
+    pub fn xim_preedit_callbacks(&self) -> bool {
+        unsafe { ffi::kime_config_xim_preedit_callbacks(self.config) }
+    }
+
+    pub fn theme(&self) -> Theme {
+        unsafe { ffi::kime_config_theme(self.config) }
+    }
+
+    pub fn mode_indicator(&self) -> ModeIndicator {
+        unsafe { ffi::kime_config_mode_indicator(self.config) }
+    }
 }
 
 impl Drop for Config {
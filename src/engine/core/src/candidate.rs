@@ -0,0 +1,133 @@
+use ahash::AHashMap;
+
+/// Count of candidates in one page, selected by number keys `1`-`9`
+pub const CANDIDATE_PAGE_SIZE: usize = 9;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Candidate {
+    pub value: String,
+    pub comment: String,
+}
+
+/// Hanja dictionary in libhangul `hanja.txt` format
+///
+/// Each line is `hangul:hanja:comment`, lines starting with `#` are ignored
+#[derive(Clone, Debug, Default)]
+pub struct HanjaDict {
+    entries: AHashMap<String, Vec<Candidate>>,
+}
+
+impl HanjaDict {
+    pub fn load_from(content: &str) -> Self {
+        let mut entries: AHashMap<String, Vec<Candidate>> = AHashMap::new();
+
+        for line in content.lines() {
+            if line.starts_with('#') {
+                continue;
+            }
+
+            let mut parts = line.splitn(3, ':');
+
+            let (key, value) = match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) if !key.is_empty() && !value.is_empty() => (key, value),
+                _ => continue,
+            };
+
+            entries.entry(key.into()).or_default().push(Candidate {
+                value: value.into(),
+                comment: parts.next().unwrap_or_default().into(),
+            });
+        }
+
+        Self { entries }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, hangul: &str) -> Option<&[Candidate]> {
+        self.entries.get(hangul).map(Vec::as_slice)
+    }
+}
+
+/// Opened list of candidates
+#[derive(Clone, Debug)]
+pub struct CandidateList {
+    items: Vec<Candidate>,
+    selected: usize,
+    /// Characters to delete before cursor when candidate is from surrounding text
+    surrounding: usize,
+}
+
+impl CandidateList {
+    pub(crate) fn new(items: Vec<Candidate>, surrounding: usize) -> Self {
+        Self {
+            items,
+            selected: 0,
+            surrounding,
+        }
+    }
+
+    pub(crate) fn surrounding(&self) -> usize {
+        self.surrounding
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Index of selected candidate in whole list
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    pub fn selected_candidate(&self) -> &Candidate {
+        &self.items[self.selected]
+    }
+
+    pub fn page(&self) -> usize {
+        self.selected / CANDIDATE_PAGE_SIZE
+    }
+
+    pub fn page_count(&self) -> usize {
+        match self.items.len() {
+            0 => 0,
+            len => (len - 1) / CANDIDATE_PAGE_SIZE + 1,
+        }
+    }
+
+    /// Candidates of current page
+    pub fn page_items(&self) -> &[Candidate] {
+        let start = self.page() * CANDIDATE_PAGE_SIZE;
+        let end = (start + CANDIDATE_PAGE_SIZE).min(self.items.len());
+        &self.items[start..end]
+    }
+
+    /// Get candidate by index of current page, used by number keys
+    pub fn page_item(&self, index: usize) -> Option<&Candidate> {
+        self.page_items().get(index)
+    }
+
+    pub(crate) fn select_next(&mut self) {
+        self.selected = (self.selected + 1) % self.items.len();
+    }
+
+    pub(crate) fn select_prev(&mut self) {
+        self.selected = self.selected.checked_sub(1).unwrap_or(self.items.len() - 1);
+    }
+
+    pub(crate) fn next_page(&mut self) {
+        let page = (self.page() + 1) % self.page_count();
+        self.selected = page * CANDIDATE_PAGE_SIZE;
+    }
+
+    pub(crate) fn prev_page(&mut self) {
+        let page = self.page().checked_sub(1).unwrap_or(self.page_count() - 1);
+        self.selected = page * CANDIDATE_PAGE_SIZE;
+    }
+}
@@ -1,8 +1,18 @@
-use crate::{keycode::Key, KeyCode, Layout, ModifierState};
-use ahash::AHashMap;
+use crate::{
+    candidate::HanjaDict,
+    keycode::{Key, KeySequence},
+    KeyCode, Layout, ModifierState,
+};
+use ahash::{AHashMap, AHashSet};
 use enumset::{EnumSet, EnumSetType};
+use once_cell::sync::OnceCell;
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Hanja dictionary installed by libhangul, used when user doesn't have one
+const LIBHANGUL_HANJA_DICT: &str = "/usr/share/libhangul/hanja/hanja.txt";
 
 #[derive(Hash, Serialize, Deserialize, EnumSetType)]
 #[enumset(serialize_as_list)]
@@ -27,34 +37,362 @@ pub enum HotkeyBehavior {
     ToHangul,
     ToEnglish,
     Commit,
+    /// Discard preedit without commit
+    Cancel,
+    BackspaceJamo,
+    BackspaceSyllable,
+    BackspaceWord,
     Emoji,
     Hanja,
 }
 
-#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+/// How much preedit is deleted by backspace
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BackspaceMode {
+    /// 강 -> 가
+    Jamo,
+    /// 강 -> (empty)
+    Syllable,
+    /// Whole word in `word_commit` mode, same as `Syllable` otherwise
+    Word,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
 pub enum HotkeyResult {
     Consume,
     Bypass,
     ConsumeIfProcessed,
 }
 
-#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HotkeyTrigger {
+    /// Trigger when key is pressed
+    #[default]
+    Press,
+    /// Trigger when key is released without pressing other key in `tap_hotkey_timeout`
+    Tap,
+}
+
+impl HotkeyTrigger {
+    fn is_press(&self) -> bool {
+        *self == Self::Press
+    }
+}
+
+/// What to do with preedit
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PreeditAction {
+    Commit,
+    Discard,
+    Keep,
+}
+
+/// What to do with preedit when key is not handled by engine
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct CommitPolicy {
+    /// Arrow, Home, End, PageUp, PageDown keys
+    pub navigation: PreeditAction,
+    /// Control, Alt, Super keys pressed alone, Shift always keep preedit
+    pub modifier: PreeditAction,
+    /// Keys pressed with Control, Alt or Super
+    pub shortcut: PreeditAction,
+    /// Other keys not in layout
+    pub other: PreeditAction,
+    /// Focus out of input context
+    pub focus_out: PreeditAction,
+}
+
+impl Default for CommitPolicy {
+    fn default() -> Self {
+        Self {
+            navigation: PreeditAction::Commit,
+            modifier: PreeditAction::Commit,
+            shortcut: PreeditAction::Commit,
+            other: PreeditAction::Commit,
+            focus_out: PreeditAction::Commit,
+        }
+    }
+}
+
+/// What to do with Space key in `word_commit` mode
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpaceAction {
+    /// Commit word and pass Space key to client
+    Bypass,
+    /// Commit word with space
+    Commit,
+}
+
+/// Rules to end word in `word_commit` mode
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct WordBoundary {
+    /// Commit word when punctuation in layout is typed, otherwise it's added to word
+    pub punctuation: bool,
+    /// Commit word when digit is typed, otherwise it's added to word
+    pub digit: bool,
+    /// Commit word when new character starts after this many characters, `0` means no limit
+    pub max_length: usize,
+    pub space: SpaceAction,
+}
+
+impl Default for WordBoundary {
+    fn default() -> Self {
+        Self {
+            punctuation: true,
+            digit: true,
+            max_length: 0,
+            space: SpaceAction::Bypass,
+        }
+    }
+}
+
+impl WordBoundary {
+    /// Check `Pass` value of layout ends word
+    pub(crate) fn is_boundary(&self, s: &str) -> bool {
+        if s.chars().all(|c| c.is_ascii_digit()) {
+            self.digit
+        } else {
+            self.punctuation
+        }
+    }
+}
+
+/// RGB color written as `#rrggbb` in config
+#[repr(C)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+}
+
+impl std::str::FromStr for Color {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s
+            .strip_prefix('#')
+            .filter(|hex| hex.len() == 6 && hex.is_ascii())
+            .ok_or_else(|| format!("Color must be #rrggbb but got {}", s))?;
+        let channel = |i: usize| {
+            u8::from_str_radix(&hex[i..i + 2], 16)
+                .map_err(|_| format!("Invalid hex digit in color {}", s))
+        };
+
+        Ok(Self::new(channel(0)?, channel(2)?, channel(4)?))
+    }
+}
+
+impl std::convert::TryFrom<String> for Color {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Color> for String {
+    fn from(c: Color) -> Self {
+        format!("#{:02x}{:02x}{:02x}", c.r, c.g, c.b)
+    }
+}
+
+/// Badge of input mode shown near cursor when mode is changed
+#[repr(C)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct ModeIndicator {
+    pub enable: bool,
+    /// Time to show indicator in milliseconds
+    pub duration: u64,
+}
+
+impl Default for ModeIndicator {
+    fn default() -> Self {
+        Self {
+            enable: true,
+            duration: 1000,
+        }
+    }
+}
+
+/// Look of windows drawn by frontend itself, sizes are in pixels at 96 DPI
+#[repr(C)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct Theme {
+    pub background: Color,
+    pub foreground: Color,
+    /// Background of selected candidate and highlighted preedit
+    pub highlight: Color,
+    pub border: Color,
+    /// Space between border and text
+    pub padding: f64,
+    /// `0` hides border
+    pub border_width: f64,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            background: Color::new(0xff, 0xff, 0xff),
+            foreground: Color::new(0x00, 0x00, 0x00),
+            highlight: Color::new(0xcc, 0xcc, 0xcc),
+            border: Color::new(0x80, 0x80, 0x80),
+            padding: 6.0,
+            border_width: 1.0,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputMode {
+    Hangul,
+    English,
+}
+
+/// Purpose of input field, same values as content purpose of text-input protocol
+#[repr(u32)]
+#[derive(
+    Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+pub enum InputPurpose {
+    #[default]
+    Normal = 0,
+    Alpha = 1,
+    Digits = 2,
+    Number = 3,
+    Phone = 4,
+    Url = 5,
+    Email = 6,
+    Name = 7,
+    Password = 8,
+    Pin = 9,
+    Date = 10,
+    Time = 11,
+    Datetime = 12,
+    Terminal = 13,
+}
+
+impl InputPurpose {
+    /// Secret field, keys and text of it must never be logged or sent to other process
+    pub const fn is_sensitive(self) -> bool {
+        matches!(self, Self::Password | Self::Pin)
+    }
+}
+
+/// Hotkey is only triggered when every given condition is satisfied
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct HotkeyCondition {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<InputMode>,
+    /// Whether engine has preedit
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preedit: Option<bool>,
+    /// Name of active layout
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub layout: Option<String>,
+    /// Program names given by frontend, empty means every program
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub apps: Vec<String>,
+}
+
+impl HotkeyCondition {
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// `mode` is called only when other conditions are met and condition has mode
+    pub(crate) fn check(
+        &self,
+        mode: impl FnOnce() -> InputMode,
+        preedit: bool,
+        layout: &str,
+        app: &str,
+    ) -> bool {
+        self.preedit.iter().all(|&p| p == preedit)
+            && self.layout.iter().all(|l| l == layout)
+            && (self.apps.is_empty() || self.apps.iter().any(|a| a == app))
+            && match self.mode {
+                Some(m) => m == mode(),
+                None => true,
+            }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Hotkey {
     behavior: HotkeyBehavior,
     result: HotkeyResult,
+    #[serde(default, skip_serializing_if = "HotkeyTrigger::is_press")]
+    trigger: HotkeyTrigger,
+    #[serde(default, skip_serializing_if = "HotkeyCondition::is_empty")]
+    condition: HotkeyCondition,
 }
 
 impl Hotkey {
-    pub const fn new(behavior: HotkeyBehavior, result: HotkeyResult) -> Self {
-        Self { behavior, result }
+    pub fn new(behavior: HotkeyBehavior, result: HotkeyResult) -> Self {
+        Self {
+            behavior,
+            result,
+            trigger: HotkeyTrigger::Press,
+            condition: HotkeyCondition::default(),
+        }
     }
 
-    pub const fn behavior(self) -> HotkeyBehavior {
+    pub fn with_trigger(self, trigger: HotkeyTrigger) -> Self {
+        Self { trigger, ..self }
+    }
+
+    pub fn with_condition(self, condition: HotkeyCondition) -> Self {
+        Self { condition, ..self }
+    }
+
+    pub fn behavior(&self) -> HotkeyBehavior {
         self.behavior
     }
-    pub const fn result(self) -> HotkeyResult {
+    pub fn result(&self) -> HotkeyResult {
         self.result
     }
+    pub fn trigger(&self) -> HotkeyTrigger {
+        self.trigger
+    }
+    pub fn condition(&self) -> &HotkeyCondition {
+        &self.condition
+    }
+}
+
+/// Hotkeys bound to same key, first hotkey which condition is satisfied is used
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum HotkeyList {
+    One(Hotkey),
+    Many(Vec<Hotkey>),
+}
+
+impl HotkeyList {
+    pub fn as_slice(&self) -> &[Hotkey] {
+        match self {
+            Self::One(hotkey) => std::slice::from_ref(hotkey),
+            Self::Many(hotkeys) => hotkeys,
+        }
+    }
+}
+
+impl From<Hotkey> for HotkeyList {
+    fn from(hotkey: Hotkey) -> Self {
+        Self::One(hotkey)
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -63,9 +401,26 @@ pub struct RawConfig {
     pub layout: String,
     pub global_hangul_state: bool,
     pub word_commit: bool,
-    pub hotkeys: BTreeMap<Key, Hotkey>,
+    pub word_boundary: WordBoundary,
+    pub backspace_mode: BackspaceMode,
+    /// Load committed syllable before cursor into preedit on Backspace when frontend knows surrounding text
+    pub reconvert_backspace: bool,
+    pub ignore_caps_lock: bool,
+    pub hotkeys: BTreeMap<Key, HotkeyList>,
+    /// Timeout of tap hotkeys in milliseconds
+    pub tap_hotkey_timeout: u64,
+    pub sequence_hotkeys: BTreeMap<KeySequence, Hotkey>,
+    /// Timeout between keys of sequence hotkeys in milliseconds
+    pub sequence_hotkey_timeout: u64,
+    pub commit_policy: CommitPolicy,
+    /// Input mode forced in field with given purpose, previous mode is restored after leaving it
+    pub purpose_modes: BTreeMap<InputPurpose, InputMode>,
     pub layout_addons: BTreeMap<String, EnumSet<Addon>>,
     pub xim_preedit_font: (String, f64),
+    /// Advertise on-the-spot (preedit callbacks) style in kime-xim, Java AWT breaks with it
+    pub xim_preedit_callbacks: bool,
+    pub theme: Theme,
+    pub mode_indicator: ModeIndicator,
 }
 
 impl Default for RawConfig {
@@ -74,7 +429,11 @@ impl Default for RawConfig {
             layout: "dubeolsik".to_string(),
             global_hangul_state: false,
             word_commit: false,
-            hotkeys: [
+            word_boundary: WordBoundary::default(),
+            backspace_mode: BackspaceMode::Jamo,
+            reconvert_backspace: false,
+            ignore_caps_lock: false,
+            hotkeys: vec![
                 (
                     Key::normal(KeyCode::Esc),
                     Hotkey::new(HotkeyBehavior::ToEnglish, HotkeyResult::Bypass),
@@ -112,8 +471,24 @@ impl Default for RawConfig {
                     Hotkey::new(HotkeyBehavior::Hanja, HotkeyResult::Consume),
                 ),
             ]
-            .iter()
-            .copied()
+            .into_iter()
+            .map(|(key, hotkey)| (key, hotkey.into()))
+            .collect(),
+            tap_hotkey_timeout: 300,
+            sequence_hotkeys: BTreeMap::new(),
+            sequence_hotkey_timeout: 1000,
+            commit_policy: CommitPolicy::default(),
+            purpose_modes: vec![
+                InputPurpose::Digits,
+                InputPurpose::Number,
+                InputPurpose::Phone,
+                InputPurpose::Url,
+                InputPurpose::Email,
+                InputPurpose::Password,
+                InputPurpose::Pin,
+            ]
+            .into_iter()
+            .map(|purpose| (purpose, InputMode::English))
             .collect(),
             layout_addons: vec![
                 ("all".into(), EnumSet::only(Addon::ComposeChoseongSsang)),
@@ -125,17 +500,37 @@ impl Default for RawConfig {
             .into_iter()
             .collect(),
             xim_preedit_font: ("D2Coding".to_string(), 15.0),
+            xim_preedit_callbacks: false,
+            theme: Theme::default(),
+            mode_indicator: ModeIndicator::default(),
         }
     }
 }
 
 pub struct Config {
     pub(crate) layout: Layout,
+    layout_name: String,
     pub(crate) global_hangul_state: bool,
-    pub(crate) hotkeys: AHashMap<Key, Hotkey>,
+    pub(crate) hotkeys: AHashMap<Key, HotkeyList>,
+    tap_hotkey_timeout: Duration,
+    sequence_hotkeys: AHashMap<Vec<Key>, Hotkey>,
+    sequence_prefixes: AHashSet<Vec<Key>>,
+    sequence_hotkey_timeout: Duration,
+    commit_policy: CommitPolicy,
+    purpose_modes: AHashMap<InputPurpose, InputMode>,
     layout_addons: EnumSet<Addon>,
     word_commit: bool,
+    word_boundary: WordBoundary,
+    backspace_mode: BackspaceMode,
+    reconvert_backspace: bool,
+    ignore_caps_lock: bool,
+    /// Files to find `hanja.txt`, first readable one is loaded when dictionary is used first
+    hanja_dict_paths: Vec<PathBuf>,
+    hanja_dict: OnceCell<HanjaDict>,
     pub xim_preedit_font: (String, f64),
+    xim_preedit_callbacks: bool,
+    theme: Theme,
+    mode_indicator: ModeIndicator,
 }
 
 impl Default for Config {
@@ -150,6 +545,10 @@ impl Config {
             layout,
             global_hangul_state: raw.global_hangul_state,
             word_commit: raw.word_commit,
+            word_boundary: raw.word_boundary,
+            backspace_mode: raw.backspace_mode,
+            reconvert_backspace: raw.reconvert_backspace,
+            ignore_caps_lock: raw.ignore_caps_lock,
             layout_addons: raw
                 .layout_addons
                 .get("all")
@@ -162,11 +561,42 @@ impl Config {
                         .unwrap_or_default(),
                 ),
             hotkeys: raw.hotkeys.into_iter().collect(),
+            layout_name: raw.layout,
+            tap_hotkey_timeout: Duration::from_millis(raw.tap_hotkey_timeout),
+            sequence_prefixes: raw
+                .sequence_hotkeys
+                .keys()
+                .flat_map(|seq| (1..seq.0.len()).map(move |len| seq.0[..len].to_vec()))
+                .collect(),
+            sequence_hotkeys: raw
+                .sequence_hotkeys
+                .into_iter()
+                .map(|(seq, hotkey)| (seq.0, hotkey))
+                .collect(),
+            sequence_hotkey_timeout: Duration::from_millis(raw.sequence_hotkey_timeout),
+            commit_policy: raw.commit_policy,
+            purpose_modes: raw.purpose_modes.into_iter().collect(),
+            hanja_dict_paths: Vec::new(),
+            hanja_dict: OnceCell::new(),
             xim_preedit_font: raw.xim_preedit_font,
+            xim_preedit_callbacks: raw.xim_preedit_callbacks,
+            theme: raw.theme,
+            mode_indicator: raw.mode_indicator,
         }
     }
 
     pub fn from_raw_config(raw: RawConfig, dir: Option<xdg::BaseDirectories>) -> Self {
+        // Dictionary is large, don't read it until hanja candidates are needed
+        let hanja_dict_paths = dir
+            .as_ref()
+            .map(|dir| {
+                dir.find_data_file("hanja.txt")
+                    .into_iter()
+                    .chain(std::iter::once(PathBuf::from(LIBHANGUL_HANJA_DICT)))
+                    .collect()
+            })
+            .unwrap_or_default();
+
         let layout = dir
             .and_then(|dir| {
                 dir.list_config_files("layouts")
@@ -200,7 +630,16 @@ impl Config {
                 load_builtin_layout!("dubeolsik", "sebeolsik-390", "sebeolsik-391", "sebeolsik-sin1995")
             });
 
-        Self::new(layout, raw)
+        Self {
+            hanja_dict_paths,
+            ..Self::new(layout, raw)
+        }
+    }
+
+    pub fn with_hanja_dict(mut self, hanja_dict: HanjaDict) -> Self {
+        self.hanja_dict = OnceCell::new();
+        self.hanja_dict.set(hanja_dict).ok();
+        self
     }
 
     pub fn load_from_config_dir() -> Option<Self> {
@@ -218,7 +657,135 @@ impl Config {
         self.word_commit
     }
 
+    pub fn word_boundary(&self) -> WordBoundary {
+        self.word_boundary
+    }
+
+    pub fn backspace_mode(&self) -> BackspaceMode {
+        self.backspace_mode
+    }
+
+    pub(crate) fn hotkey(
+        &self,
+        key: Key,
+        mut mode: impl FnMut() -> InputMode,
+        preedit: bool,
+        app: &str,
+    ) -> Option<&Hotkey> {
+        self.hotkeys
+            .get(&key)
+            .or_else(|| self.hotkeys.get(&key.without_locks()))?
+            .as_slice()
+            .iter()
+            .find(|hotkey| {
+                hotkey
+                    .condition
+                    .check(&mut mode, preedit, &self.layout_name, app)
+            })
+    }
+
+    pub(crate) fn sequence_hotkey(
+        &self,
+        keys: &[Key],
+        mode: impl FnOnce() -> InputMode,
+        preedit: bool,
+        app: &str,
+    ) -> Option<&Hotkey> {
+        self.sequence_hotkeys.get(keys).filter(|hotkey| {
+            hotkey
+                .condition
+                .check(mode, preedit, &self.layout_name, app)
+        })
+    }
+
+    pub fn layout_name(&self) -> &str {
+        &self.layout_name
+    }
+
+    pub(crate) fn is_sequence_prefix(&self, keys: &[Key]) -> bool {
+        self.sequence_prefixes.contains(keys)
+    }
+
+    pub fn sequence_hotkey_timeout(&self) -> Duration {
+        self.sequence_hotkey_timeout
+    }
+
+    pub fn purpose_mode(&self, purpose: InputPurpose) -> Option<InputMode> {
+        self.purpose_modes.get(&purpose).copied()
+    }
+
+    pub fn commit_policy(&self) -> CommitPolicy {
+        self.commit_policy
+    }
+
+    pub fn tap_hotkey_timeout(&self) -> Duration {
+        self.tap_hotkey_timeout
+    }
+
+    pub fn reconvert_backspace(&self) -> bool {
+        self.reconvert_backspace
+    }
+
+    /// Load dictionary at first call
+    pub fn hanja_dict(&self) -> &HanjaDict {
+        self.hanja_dict.get_or_init(|| {
+            self.hanja_dict_paths
+                .iter()
+                .find_map(|path| std::fs::read_to_string(path).ok())
+                .map(|content| HanjaDict::load_from(&content))
+                .unwrap_or_default()
+        })
+    }
+
+    pub fn ignore_caps_lock(&self) -> bool {
+        self.ignore_caps_lock
+    }
+
+    pub fn xim_preedit_callbacks(&self) -> bool {
+        self.xim_preedit_callbacks
+    }
+
+    pub fn theme(&self) -> Theme {
+        self.theme
+    }
+
+    pub fn mode_indicator(&self) -> ModeIndicator {
+        self.mode_indicator
+    }
+
     pub fn check_addon(&self, addon: Addon) -> bool {
         self.layout_addons.contains(addon)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn theme() {
+        let theme: Theme = serde_yaml::from_str("background: '#1E1e2e'\npadding: 4.0\n").unwrap();
+
+        assert_eq!(theme.background, Color::new(0x1e, 0x1e, 0x2e));
+        assert_eq!(theme.padding, 4.0);
+        assert_eq!(theme.foreground, Theme::default().foreground);
+        assert!(serde_yaml::from_str::<Theme>("border: '#fff'").is_err());
+        assert!(serde_yaml::from_str::<Theme>("border: '#gggggg'").is_err());
+        assert_eq!(String::from(theme.background), "#1e1e2e");
+    }
+
+    #[test]
+    fn hotkey_condition_lazy_mode() {
+        let condition = HotkeyCondition::default();
+        assert!(condition.check(|| panic!("mode is not needed"), false, "", ""));
+
+        let condition = HotkeyCondition {
+            mode: Some(InputMode::Hangul),
+            preedit: Some(true),
+            ..Default::default()
+        };
+        assert!(!condition.check(|| panic!("preedit is unmatched"), false, "", ""));
+        assert!(condition.check(|| InputMode::Hangul, true, "", ""));
+        assert!(!condition.check(|| InputMode::English, true, "", ""));
+    }
+}
@@ -6,6 +6,14 @@ bitflags::bitflags! {
         const HAS_PREEDIT = 0b100;
         const NEED_RESET = 0b1000;
         const NEED_FLUSH = 0b10000;
+        /// Key is part of pending hotkey sequence, frontend should keep this key to replay it later
+        const SEQUENCE_PENDING = 0b100000;
+        /// Pending hotkey sequence is unmatched, frontend should send kept keys to client
+        const REPLAY_SEQUENCE = 0b1000000;
+        /// Frontend should delete `delete_surrounding` characters before cursor
+        const DELETE_SURROUNDING = 0b10000000;
+        /// Candidate list is opened, frontend should show `candidates`
+        const HAS_CANDIDATE = 0b100000000;
     }
 }
 
@@ -4,7 +4,7 @@ use serde::{
     de::{Error, Unexpected},
     Deserialize, Serialize,
 };
-use strum::{Display, EnumString};
+use strum::{Display, EnumIter, EnumString};
 
 bitflags::bitflags! {
     #[repr(transparent)]
@@ -13,12 +13,15 @@ bitflags::bitflags! {
         const SUPER = 0x2;
         const SHIFT = 0x4;
         const ALT = 0x8;
+        const CAPSLOCK = 0x10;
+        const NUMLOCK = 0x20;
     }
 }
 
-// TODO: complete
 #[repr(u32)]
-#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, EnumString, Display)]
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, EnumString, Display, EnumIter,
+)]
 pub enum KeyCode {
     #[strum(to_string = "1")]
     One,
@@ -85,7 +88,8 @@ pub enum KeyCode {
     M,
 
     Esc,
-    Shift,
+    ShiftL,
+    ShiftR,
     Backspace,
     Enter,
     Tab,
@@ -98,6 +102,48 @@ pub enum KeyCode {
     AltR,
     Hangul,
     HangulHanja,
+    SuperL,
+    SuperR,
+    CapsLock,
+    NumLock,
+    ScrollLock,
+    Menu,
+    Print,
+    Pause,
+
+    Insert,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Left,
+    Right,
+    Up,
+    Down,
+
+    Kp0,
+    Kp1,
+    Kp2,
+    Kp3,
+    Kp4,
+    Kp5,
+    Kp6,
+    Kp7,
+    Kp8,
+    Kp9,
+    KpPlus,
+    KpMinus,
+    KpMultiply,
+    KpDivide,
+    KpPeriod,
+    KpComma,
+    KpEqual,
+    KpEnter,
+
+    AudioPlay,
+    AudioStop,
+    AudioPrev,
+    AudioNext,
 
     F1,
     F2,
@@ -111,6 +157,18 @@ pub enum KeyCode {
     F10,
     F11,
     F12,
+    F13,
+    F14,
+    F15,
+    F16,
+    F17,
+    F18,
+    F19,
+    F20,
+    F21,
+    F22,
+    F23,
+    F24,
 }
 
 impl KeyCode {
@@ -130,8 +188,8 @@ impl KeyCode {
             21 => Some(Self::Equal),
             34 => Some(Self::OpenBracket),
             35 => Some(Self::CloseBracket),
-            // Shift_L, Shift_R
-            50 | 62 => Some(Self::Shift),
+            50 => Some(Self::ShiftL),
+            62 => Some(Self::ShiftR),
             51 => Some(Self::Backslash),
             61 => Some(Self::Slash),
             47 => Some(Self::SemiColon),
@@ -182,8 +240,52 @@ impl KeyCode {
             102 => Some(Self::Muhenkan),
             64 => Some(Self::AltL),
             108 => Some(Self::AltR),
+            // Some keyboards send Hangul, Hanja keys with keycodes of volume keys
+            // so volume keys can't be mapped
             122 | 130 => Some(Self::Hangul),
             121 | 123 | 131 => Some(Self::HangulHanja),
+            133 => Some(Self::SuperL),
+            134 => Some(Self::SuperR),
+            66 => Some(Self::CapsLock),
+            77 => Some(Self::NumLock),
+            78 => Some(Self::ScrollLock),
+            135 => Some(Self::Menu),
+            107 => Some(Self::Print),
+            127 => Some(Self::Pause),
+
+            118 => Some(Self::Insert),
+            110 => Some(Self::Home),
+            115 => Some(Self::End),
+            112 => Some(Self::PageUp),
+            117 => Some(Self::PageDown),
+            113 => Some(Self::Left),
+            114 => Some(Self::Right),
+            111 => Some(Self::Up),
+            116 => Some(Self::Down),
+
+            90 => Some(Self::Kp0),
+            87 => Some(Self::Kp1),
+            88 => Some(Self::Kp2),
+            89 => Some(Self::Kp3),
+            83 => Some(Self::Kp4),
+            84 => Some(Self::Kp5),
+            85 => Some(Self::Kp6),
+            79 => Some(Self::Kp7),
+            80 => Some(Self::Kp8),
+            81 => Some(Self::Kp9),
+            86 => Some(Self::KpPlus),
+            82 => Some(Self::KpMinus),
+            63 => Some(Self::KpMultiply),
+            106 => Some(Self::KpDivide),
+            91 => Some(Self::KpPeriod),
+            129 => Some(Self::KpComma),
+            125 => Some(Self::KpEqual),
+            104 => Some(Self::KpEnter),
+
+            172 => Some(Self::AudioPlay),
+            174 => Some(Self::AudioStop),
+            173 => Some(Self::AudioPrev),
+            171 => Some(Self::AudioNext),
 
             67 => Some(Self::F1),
             68 => Some(Self::F2),
@@ -195,12 +297,54 @@ impl KeyCode {
             74 => Some(Self::F8),
             75 => Some(Self::F9),
             76 => Some(Self::F10),
-            77 => Some(Self::F11),
-            78 => Some(Self::F12),
+            95 => Some(Self::F11),
+            96 => Some(Self::F12),
+            191 => Some(Self::F13),
+            192 => Some(Self::F14),
+            193 => Some(Self::F15),
+            194 => Some(Self::F16),
+            195 => Some(Self::F17),
+            196 => Some(Self::F18),
+            197 => Some(Self::F19),
+            198 => Some(Self::F20),
+            199 => Some(Self::F21),
+            200 => Some(Self::F22),
+            201 => Some(Self::F23),
+            202 => Some(Self::F24),
 
             _ => None,
         }
     }
+
+    /// Is this key used to move cursor
+    pub const fn is_navigation(self) -> bool {
+        matches!(
+            self,
+            Self::Left
+                | Self::Right
+                | Self::Up
+                | Self::Down
+                | Self::Home
+                | Self::End
+                | Self::PageUp
+                | Self::PageDown
+        )
+    }
+
+    /// Is this key only used to change modifier state
+    pub const fn is_modifier(self) -> bool {
+        matches!(
+            self,
+            Self::ShiftL
+                | Self::ShiftR
+                | Self::ControlL
+                | Self::ControlR
+                | Self::AltL
+                | Self::AltR
+                | Self::SuperL
+                | Self::SuperR
+        )
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
@@ -233,10 +377,26 @@ impl Key {
     pub const fn super_(code: KeyCode) -> Self {
         Self::new(code, ModifierState::SUPER)
     }
+
+    /// Same key without CapsLock, NumLock state
+    pub fn without_locks(self) -> Self {
+        Self::new(
+            self.code,
+            self.state - (ModifierState::CAPSLOCK | ModifierState::NUMLOCK),
+        )
+    }
 }
 
 impl fmt::Display for Key {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.state.contains(ModifierState::CAPSLOCK) {
+            f.write_str("Caps-")?;
+        }
+
+        if self.state.contains(ModifierState::NUMLOCK) {
+            f.write_str("Num-")?;
+        }
+
         if self.state.contains(ModifierState::SUPER) {
             f.write_str("Super-")?;
         }
@@ -264,6 +424,18 @@ impl FromStr for Key {
         let mut state = ModifierState::empty();
 
         loop {
+            if let Some(n) = s.strip_prefix("Caps-") {
+                s = n;
+                state |= ModifierState::CAPSLOCK;
+                continue;
+            }
+
+            if let Some(n) = s.strip_prefix("Num-") {
+                s = n;
+                state |= ModifierState::NUMLOCK;
+                continue;
+            }
+
             if let Some(n) = s.strip_prefix("Super-") {
                 s = n;
                 state |= ModifierState::SUPER;
@@ -316,6 +488,59 @@ impl<'de> Deserialize<'de> for Key {
     }
 }
 
+/// Keys pressed in order e.g. `C-SemiColon H`
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct KeySequence(pub Vec<Key>);
+
+impl fmt::Display for KeySequence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, key) in self.0.iter().enumerate() {
+            if i != 0 {
+                f.write_str(" ")?;
+            }
+
+            write!(f, "{}", key)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for KeySequence {
+    type Err = <KeyCode as FromStr>::Err;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split_whitespace()
+            .map(Key::from_str)
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+impl Serialize for KeySequence {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let s = self.to_string();
+        serializer.serialize_str(&s)
+    }
+}
+
+impl<'de> Deserialize<'de> for KeySequence {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+
+        match Self::from_str(&s) {
+            Ok(seq) if !seq.0.is_empty() => Ok(seq),
+            _ => Err(D::Error::invalid_value(Unexpected::Str(&s), &"KeySequence")),
+        }
+    }
+}
+
 #[test]
 fn key_parse() {
     assert_eq!(
@@ -325,4 +550,54 @@ fn key_parse() {
     assert_eq!("S-4".parse::<Key>().unwrap(), Key::shift(KeyCode::Four));
     assert_eq!("C-Space".parse::<Key>().unwrap(), Key::ctrl(KeyCode::Space));
     assert_eq!("M-X".parse::<Key>().unwrap(), Key::alt(KeyCode::X));
+    assert_eq!("S-Kp1".parse::<Key>().unwrap(), Key::shift(KeyCode::Kp1));
+    assert_eq!("C-F13".parse::<Key>().unwrap(), Key::ctrl(KeyCode::F13));
+    assert_eq!(
+        "Caps-S-R".parse::<Key>().unwrap(),
+        Key::new(KeyCode::R, ModifierState::CAPSLOCK | ModifierState::SHIFT)
+    );
+    assert_eq!(
+        "Num-Kp1".parse::<Key>().unwrap().without_locks(),
+        Key::normal(KeyCode::Kp1)
+    );
+}
+
+#[test]
+fn key_round_trip() {
+    use strum::IntoEnumIterator;
+
+    for code in KeyCode::iter() {
+        let key = Key::new(code, ModifierState::CONTROL | ModifierState::SHIFT);
+        assert_eq!(key.to_string().parse::<Key>().unwrap(), key);
+        let key = Key::new(code, ModifierState::all());
+        assert_eq!(key.to_string().parse::<Key>().unwrap(), key);
+    }
+}
+
+#[test]
+fn hardware_code_unique() {
+    use strum::IntoEnumIterator;
+
+    for code in KeyCode::iter() {
+        let count = (0..=255)
+            .filter(|&c| KeyCode::from_hardward_code(c) == Some(code))
+            .count();
+        // Keyboards use different codes for hangul keys
+        let expected = match code {
+            KeyCode::Hangul => 2,
+            KeyCode::HangulHanja => 3,
+            _ => 1,
+        };
+        assert_eq!(count, expected, "{} has unexpected hardware codes", code);
+    }
+}
+
+#[test]
+fn key_sequence_parse() {
+    let seq = "C-SemiColon H".parse::<KeySequence>().unwrap();
+    assert_eq!(
+        seq,
+        KeySequence(vec![Key::ctrl(KeyCode::SemiColon), Key::normal(KeyCode::H)])
+    );
+    assert_eq!(seq.to_string(), "C-SemiColon H");
 }
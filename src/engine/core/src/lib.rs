@@ -1,20 +1,31 @@
+mod candidate;
 mod characters;
 mod config;
 mod input_result;
 mod keycode;
+mod preedit;
 mod state;
 
 use ahash::AHashMap;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write as _;
 use std::io::{self, Read, Write};
 use std::net::Shutdown;
 use std::os::unix::net::UnixStream;
+use std::time::{Duration, Instant};
 
 use self::characters::KeyValue;
-use self::state::HangulState;
-
-pub use self::config::{Addon, Config, Hotkey, HotkeyBehavior, HotkeyResult, RawConfig};
+use self::state::{HangulSnapshot, HangulState};
+
+pub use self::candidate::{Candidate, CandidateList, HanjaDict, CANDIDATE_PAGE_SIZE};
+pub use self::config::{
+    Addon, BackspaceMode, Color, CommitPolicy, Config, Hotkey, HotkeyBehavior, HotkeyCondition,
+    HotkeyList, HotkeyResult, HotkeyTrigger, InputMode, InputPurpose, ModeIndicator, PreeditAction,
+    RawConfig, SpaceAction, Theme, WordBoundary,
+};
 pub use self::input_result::InputResult;
-pub use self::keycode::{Key, KeyCode, ModifierState};
+pub use self::keycode::{Key, KeyCode, KeySequence, ModifierState};
+pub use self::preedit::{Composition, JamoSlot, PreeditSegment, PreeditStyle};
 
 #[derive(Clone, Default)]
 pub struct Layout {
@@ -37,15 +48,52 @@ impl Layout {
         Self { keymap }
     }
 
+    fn get(&self, key: Key) -> Option<&KeyValue> {
+        self.keymap
+            .get(&key)
+            .or_else(|| self.keymap.get(&key.without_locks()))
+    }
+
     pub fn load_from(content: &str) -> Result<Self, serde_yaml::Error> {
         Ok(Self::from_items(serde_yaml::from_str(content)?))
     }
 }
 
+/// Saved state of `InputEngine` which can be restored later
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EngineSnapshot {
+    hangul: bool,
+    state: HangulSnapshot,
+}
+
+impl EngineSnapshot {
+    pub fn to_yaml(&self) -> String {
+        serde_yaml::to_string(self).unwrap_or_default()
+    }
+
+    pub fn from_yaml(content: &str) -> Result<Self, serde_yaml::Error> {
+        serde_yaml::from_str(content)
+    }
+}
+
 pub struct InputEngine {
     state: HangulState,
     enable_hangul: bool,
+    tap_key: Option<(Key, Hotkey, Instant)>,
+    sequence: Vec<Key>,
+    sequence_pressed_at: Instant,
     buf: Vec<u8>,
+    preedit_buf: String,
+    segments: Vec<PreeditSegment>,
+    app: String,
+    /// Text before cursor
+    surrounding: String,
+    delete_surrounding: usize,
+    purpose: InputPurpose,
+    /// Hangul state before entering field which forces input mode
+    saved_hangul: Option<bool>,
+    candidate_support: bool,
+    candidates: Option<CandidateList>,
 }
 
 impl Default for InputEngine {
@@ -59,7 +107,19 @@ impl InputEngine {
         Self {
             state: HangulState::new(word_commit),
             enable_hangul: false,
+            tap_key: None,
+            sequence: Vec::with_capacity(4),
+            sequence_pressed_at: Instant::now(),
             buf: Vec::with_capacity(16),
+            preedit_buf: String::with_capacity(16),
+            segments: Vec::with_capacity(4),
+            app: String::new(),
+            surrounding: String::new(),
+            delete_surrounding: 0,
+            purpose: InputPurpose::Normal,
+            saved_hangul: None,
+            candidate_support: false,
+            candidates: None,
         }
     }
 
@@ -71,6 +131,107 @@ impl InputEngine {
         self.enable_hangul
     }
 
+    /// Set program name used by hotkey condition
+    pub fn set_app(&mut self, app: &str) {
+        self.app.clear();
+        self.app.push_str(app);
+    }
+
+    /// Set text before cursor, this is cleared when engine used it
+    pub fn set_surrounding_text(&mut self, before_cursor: &str) {
+        self.surrounding.clear();
+        self.surrounding.push_str(before_cursor);
+    }
+
+    /// Frontend can show `candidates` by itself
+    ///
+    /// Hanja hotkey opens candidate list from `hanja_dict` instead of external window
+    pub fn set_candidate_support(&mut self, support: bool) {
+        self.candidate_support = support;
+    }
+
+    /// Opened candidate list, shown when result has `HAS_CANDIDATE`
+    pub fn candidates(&self) -> Option<&CandidateList> {
+        self.candidates.as_ref()
+    }
+
+    /// Count of characters to delete before cursor when result has `DELETE_SURROUNDING`
+    pub fn delete_surrounding(&self) -> usize {
+        self.delete_surrounding
+    }
+
+    /// Load committed text before cursor into preedit using surrounding text
+    fn reconvert_surrounding(&mut self, config: &Config) -> InputResult {
+        let surrounding = std::mem::take(&mut self.surrounding);
+        self.delete_surrounding = self.reconvert(&surrounding, config);
+        self.surrounding = surrounding;
+        self.surrounding.clear();
+
+        if self.delete_surrounding == 0 {
+            InputResult::empty()
+        } else {
+            InputResult::DELETE_SURROUNDING
+        }
+    }
+
+    /// Set purpose of focused field and apply `purpose_modes`
+    ///
+    /// Return `LANGUAGE_CHANGED` when hangul state is changed
+    pub fn set_purpose(&mut self, purpose: InputPurpose, config: &Config) -> InputResult {
+        let prev = self.enable_hangul;
+        self.purpose = purpose;
+
+        match config.purpose_mode(purpose) {
+            Some(mode) => {
+                self.saved_hangul.get_or_insert(self.enable_hangul);
+                self.enable_hangul = mode == InputMode::Hangul;
+            }
+            None => {
+                if let Some(saved) = self.saved_hangul.take() {
+                    self.enable_hangul = saved;
+                }
+            }
+        }
+
+        if prev == self.enable_hangul {
+            InputResult::empty()
+        } else {
+            InputResult::LANGUAGE_CHANGED
+        }
+    }
+
+    pub fn purpose(&self) -> InputPurpose {
+        self.purpose
+    }
+
+    fn input_mode(&mut self, config: &Config) -> InputMode {
+        if self.check_hangul_state(config) {
+            InputMode::Hangul
+        } else {
+            InputMode::English
+        }
+    }
+
+    fn hotkey<'c>(&mut self, key: Key, config: &'c Config) -> Option<&'c Hotkey> {
+        let preedit = self
+            .state
+            .preedit_result()
+            .contains(InputResult::HAS_PREEDIT);
+
+        // Reading global hangul state is slow, do it only when hotkey has mode condition
+        let app = std::mem::take(&mut self.app);
+        let mut mode = None;
+        let hotkey = config.hotkey(
+            key,
+            || *mode.get_or_insert_with(|| self.input_mode(config)),
+            preedit,
+            &app,
+        );
+        self.app = app;
+
+        hotkey
+    }
+
     fn read_global_hangul_state(&mut self) -> io::Result<bool> {
         let mut stream = UnixStream::connect("/tmp/kime_window.sock")?;
         stream.write_all(b"l")?;
@@ -82,7 +243,8 @@ impl InputEngine {
     }
 
     fn check_hangul_state(&mut self, config: &Config) -> bool {
-        if config.global_hangul_state {
+        // Forced mode of field is prior to global state
+        if config.global_hangul_state && self.saved_hangul.is_none() {
             self.enable_hangul = self
                 .read_global_hangul_state()
                 .unwrap_or(self.enable_hangul);
@@ -92,19 +254,28 @@ impl InputEngine {
     }
 
     fn bypass(&mut self) -> InputResult {
+        self.candidates = None;
         self.clear_preedit();
         InputResult::NEED_RESET
     }
 
     fn hanja(&mut self) -> io::Result<bool> {
+        // Don't send text of secret field to other process
+        if self.purpose.is_sensitive() {
+            return Ok(false);
+        }
+
         let mut stream = UnixStream::connect("/tmp/kime_window.sock")?;
-        let hangul = self.state.preedit_str();
+        let (hangul, delete_surrounding) = self.hanja_key();
+        self.delete_surrounding = delete_surrounding;
         stream.write_all(format!("h{}", hangul).as_bytes())?;
         stream.flush()?;
         stream.shutdown(Shutdown::Write)?;
+        self.surrounding.clear();
         let len = stream.read_to_end(&mut self.buf)?;
 
         if len == 0 {
+            self.delete_surrounding = 0;
             Ok(false)
         } else {
             let hanja = std::str::from_utf8(&self.buf[..len])
@@ -116,6 +287,110 @@ impl InputEngine {
         }
     }
 
+    /// Hangul to convert into hanja and count of characters to delete before cursor
+    fn hanja_key(&mut self) -> (String, usize) {
+        let preedit = self.state.preedit_str();
+
+        if !preedit.is_empty() {
+            return (preedit.into(), 0);
+        }
+
+        // Convert word before cursor
+        let start = self
+            .surrounding
+            .char_indices()
+            .rev()
+            .take_while(|(_, c)| ('가'..='힣').contains(c))
+            .last()
+            .map_or(self.surrounding.len(), |(idx, _)| idx);
+        let word = &self.surrounding[start..];
+
+        (word.into(), word.chars().count())
+    }
+
+    /// Open candidate list of hanja, return `false` when frontend can't show it or nothing is found
+    fn open_candidates(&mut self, config: &Config) -> bool {
+        // Don't show text of secret field
+        if !self.candidate_support || self.purpose.is_sensitive() {
+            return false;
+        }
+
+        let (hangul, delete_surrounding) = self.hanja_key();
+
+        match config.hanja_dict().get(&hangul) {
+            Some(items) if !items.is_empty() => {
+                self.surrounding.clear();
+                self.candidates = Some(CandidateList::new(items.to_vec(), delete_surrounding));
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Handle key while candidate list is opened
+    ///
+    /// Return `None` when list is closed and key should be processed as usual
+    fn candidate_key(&mut self, key: Key) -> Option<InputResult> {
+        let list = self.candidates.as_mut()?;
+
+        if key.code.is_modifier() {
+            // Wait next key with modifier
+            return Some(InputResult::HAS_CANDIDATE | self.state.preedit_result());
+        }
+
+        let state = key.without_locks().state;
+
+        if state.intersects(ModifierState::CONTROL | ModifierState::ALT | ModifierState::SUPER) {
+            self.candidates = None;
+            return None;
+        }
+
+        let digit = key.code as usize;
+
+        match key.code {
+            KeyCode::Tab if state.contains(ModifierState::SHIFT) => list.select_prev(),
+            KeyCode::Down | KeyCode::Right | KeyCode::Tab => list.select_next(),
+            KeyCode::Up | KeyCode::Left => list.select_prev(),
+            KeyCode::PageDown => list.next_page(),
+            KeyCode::PageUp => list.prev_page(),
+            KeyCode::Space | KeyCode::Enter => {
+                let candidate = list.selected_candidate().value.clone();
+                return Some(self.commit_candidate(&candidate));
+            }
+            _ if (KeyCode::One as usize..=KeyCode::Nine as usize).contains(&digit) => {
+                if let Some(candidate) = list.page_item(digit - KeyCode::One as usize) {
+                    let candidate = candidate.value.clone();
+                    return Some(self.commit_candidate(&candidate));
+                }
+            }
+            KeyCode::Esc => {
+                self.candidates = None;
+                return Some(InputResult::CONSUMED | self.state.preedit_result());
+            }
+            _ => {
+                self.candidates = None;
+                return None;
+            }
+        }
+
+        Some(InputResult::CONSUMED | InputResult::HAS_CANDIDATE | self.state.preedit_result())
+    }
+
+    /// Replace hangul with candidate and close list
+    fn commit_candidate(&mut self, candidate: &str) -> InputResult {
+        let mut ret = InputResult::CONSUMED | InputResult::NEED_RESET;
+
+        self.delete_surrounding = self.candidates.take().map_or(0, |list| list.surrounding());
+
+        if self.delete_surrounding != 0 {
+            ret |= InputResult::DELETE_SURROUNDING;
+        }
+
+        self.state.pass_replace(candidate);
+
+        ret
+    }
+
     fn emoji(&mut self) -> io::Result<bool> {
         let mut stream = UnixStream::connect("/tmp/kime_window.sock")?;
         stream.write_all(b"e")?;
@@ -142,80 +417,329 @@ impl InputEngine {
         Ok(())
     }
 
-    pub fn press_key(&mut self, key: Key, config: &Config) -> InputResult {
-        if let Some(hotkey) = config.hotkeys.get(&key) {
-            let mut processed = false;
-            let mut ret = InputResult::empty();
+    pub fn press_key(&mut self, mut key: Key, config: &Config) -> InputResult {
+        self.delete_surrounding = 0;
+
+        if config.ignore_caps_lock() {
+            key.state.remove(ModifierState::CAPSLOCK);
+        }
+
+        if let Some(ret) = self.candidate_key(key) {
+            return ret;
+        }
+
+        let mut ret = InputResult::empty();
+
+        if !self.sequence.is_empty() {
+            if key.code.is_modifier() {
+                // Wait next key with modifier
+                return self.sequence_pending();
+            }
 
-            match hotkey.behavior() {
-                HotkeyBehavior::ToEnglish => {
-                    if self.enable_hangul {
-                        self.enable_hangul = false;
-                        ret |= InputResult::LANGUAGE_CHANGED;
-                        processed = true;
-                    }
+            if self.sequence_pressed_at.elapsed() <= config.sequence_hotkey_timeout() {
+                self.sequence.push(key.without_locks());
+
+                let preedit = self
+                    .state
+                    .preedit_result()
+                    .contains(InputResult::HAS_PREEDIT);
+                let sequence = std::mem::take(&mut self.sequence);
+                let app = std::mem::take(&mut self.app);
+                let hotkey =
+                    config.sequence_hotkey(&sequence, || self.input_mode(config), preedit, &app);
+                self.sequence = sequence;
+                self.app = app;
+
+                if let Some(hotkey) = hotkey {
+                    self.sequence.clear();
+                    return self.run_hotkey(hotkey, config);
+                } else if config.is_sequence_prefix(&self.sequence) {
+                    self.sequence_pressed_at = Instant::now();
+                    return self.sequence_pending();
                 }
-                HotkeyBehavior::Emoji => {
-                    if self.emoji().unwrap_or(false) {
-                        ret |= InputResult::NEED_RESET;
-                        processed = true;
-                    }
+            }
+
+            // Unmatched or timed out, commit preedit before kept keys are replayed
+            self.sequence.clear();
+            self.state.clear_preedit();
+            ret |= InputResult::REPLAY_SEQUENCE | InputResult::NEED_FLUSH;
+        }
+
+        if config.is_sequence_prefix(&[key.without_locks()]) {
+            self.tap_key = None;
+            self.sequence.push(key.without_locks());
+            self.sequence_pressed_at = Instant::now();
+            return ret | self.sequence_pending();
+        }
+
+        ret | self.press_single_key(key, config)
+    }
+
+    fn sequence_pending(&self) -> InputResult {
+        InputResult::CONSUMED | InputResult::SEQUENCE_PENDING | InputResult::HAS_PREEDIT
+    }
+
+    /// Time left until pending hotkey sequence expires, `None` when no sequence is pending
+    ///
+    /// Timeout is checked when next key is pressed, frontend should call `expire_sequence`
+    /// after it so hint of sequence doesn't stay in preedit
+    pub fn sequence_timeout(&self, config: &Config) -> Option<Duration> {
+        if self.sequence.is_empty() {
+            None
+        } else {
+            Some(
+                config
+                    .sequence_hotkey_timeout()
+                    .checked_sub(self.sequence_pressed_at.elapsed())
+                    .unwrap_or_default(),
+            )
+        }
+    }
+
+    /// Drop pending hotkey sequence when its timeout is passed
+    ///
+    /// Preedit is committed and frontend should replay kept keys like unmatched sequence,
+    /// return empty when nothing is expired
+    pub fn expire_sequence(&mut self, config: &Config) -> InputResult {
+        if self.sequence_timeout(config) != Some(Duration::default()) {
+            return InputResult::empty();
+        }
+
+        self.sequence.clear();
+        self.state.clear_preedit();
+        InputResult::REPLAY_SEQUENCE | InputResult::NEED_FLUSH
+    }
+
+    fn press_single_key(&mut self, key: Key, config: &Config) -> InputResult {
+        let hotkey = self.hotkey(key, config);
+
+        if let Some(hotkey) = hotkey.filter(|h| h.trigger() == HotkeyTrigger::Tap) {
+            // Wait for release, keep first press time on key repeat
+            match self.tap_key {
+                Some((tap, ..)) if tap.code == key.code => {}
+                _ => self.tap_key = Some((key, hotkey.clone(), Instant::now())),
+            }
+
+            // Don't reset state
+            return self.state.preedit_result();
+        }
+
+        self.tap_key = None;
+
+        if let Some(hotkey) = hotkey {
+            self.run_hotkey(hotkey, config)
+        } else if matches!(key.code, KeyCode::ShiftL | KeyCode::ShiftR) {
+            // Don't reset state
+            self.state.preedit_result()
+        } else if key.without_locks().state.is_empty() && self.edit_word(key.code) {
+            self.state.preedit_result() | InputResult::CONSUMED
+        } else if self.check_hangul_state(config) {
+            if key.code == KeyCode::Backspace {
+                let ret = if config.reconvert_backspace() {
+                    self.reconvert_surrounding(config)
+                } else {
+                    InputResult::empty()
+                };
+                ret | self.state.backspace(config.backspace_mode(), config)
+            } else if let Some(v) = config.layout.get(key) {
+                self.state.key(v, config)
+            } else if let Some(ret) = self.word_boundary_key(key, config) {
+                ret
+            } else {
+                self.bypass_key(key, config)
+            }
+        } else {
+            self.bypass_key(key, config)
+        }
+    }
+
+    /// Move cursor or delete character in word preedit
+    fn edit_word(&mut self, code: KeyCode) -> bool {
+        match code {
+            KeyCode::Left => self.state.cursor_left(),
+            KeyCode::Right => self.state.cursor_right(),
+            KeyCode::Home => {
+                let mut moved = false;
+                while self.state.cursor_left() {
+                    moved = true;
                 }
-                HotkeyBehavior::Hanja => {
-                    if self.hanja().unwrap_or(false) {
-                        ret |= InputResult::NEED_RESET;
-                        processed = true;
-                    }
+                moved
+            }
+            KeyCode::End => {
+                let mut moved = false;
+                while self.state.cursor_right() {
+                    moved = true;
                 }
-                HotkeyBehavior::ToHangul => {
-                    if !self.enable_hangul {
-                        self.enable_hangul = true;
-                        ret |= InputResult::LANGUAGE_CHANGED;
-                        processed = true;
-                    }
+                moved
+            }
+            KeyCode::Delete => self.state.delete(),
+            _ => false,
+        }
+    }
+
+    /// Handle Space key using `WordBoundary`
+    fn word_boundary_key(&mut self, key: Key, config: &Config) -> Option<InputResult> {
+        if self.state.word_commit()
+            && key.code == KeyCode::Space
+            && key.without_locks().state.is_empty()
+            && config.word_boundary().space == SpaceAction::Commit
+            && self
+                .state
+                .preedit_result()
+                .contains(InputResult::HAS_PREEDIT)
+        {
+            self.state.pass(" ");
+            Some(InputResult::NEED_RESET | InputResult::CONSUMED)
+        } else {
+            None
+        }
+    }
+
+    /// Bypass key which is not handled by engine using `CommitPolicy`
+    fn bypass_key(&mut self, key: Key, config: &Config) -> InputResult {
+        let policy = config.commit_policy();
+
+        let action = if key.code.is_modifier() {
+            policy.modifier
+        } else if key
+            .state
+            .intersects(ModifierState::CONTROL | ModifierState::ALT | ModifierState::SUPER)
+        {
+            policy.shortcut
+        } else if key.code.is_navigation() {
+            policy.navigation
+        } else {
+            policy.other
+        };
+
+        self.preedit_action(action)
+    }
+
+    fn preedit_action(&mut self, action: PreeditAction) -> InputResult {
+        match action {
+            PreeditAction::Commit => self.bypass(),
+            PreeditAction::Discard => {
+                self.state.discard_preedit();
+                InputResult::NEED_RESET
+            }
+            PreeditAction::Keep => self.state.preedit_result(),
+        }
+    }
+
+    /// Release key, this is needed for tap hotkeys
+    ///
+    /// Return empty result when nothing happened
+    pub fn release_key(&mut self, key: Key, config: &Config) -> InputResult {
+        match self.tap_key.take() {
+            Some((tap, hotkey, pressed_at)) if tap.code == key.code => {
+                if pressed_at.elapsed() > config.tap_hotkey_timeout() {
+                    return InputResult::empty();
                 }
-                HotkeyBehavior::ToggleHangul => {
-                    self.enable_hangul = !self.enable_hangul;
+
+                // Release event must be delivered to client
+                self.run_hotkey(&hotkey, config) - InputResult::CONSUMED
+            }
+            tap_key => {
+                self.tap_key = tap_key;
+                InputResult::empty()
+            }
+        }
+    }
+
+    fn run_hotkey(&mut self, hotkey: &Hotkey, config: &Config) -> InputResult {
+        let mut processed = false;
+        let mut ret = InputResult::empty();
+
+        match hotkey.behavior() {
+            HotkeyBehavior::ToEnglish => {
+                if self.check_hangul_state(config) {
+                    self.enable_hangul = false;
                     ret |= InputResult::LANGUAGE_CHANGED;
                     processed = true;
                 }
-                HotkeyBehavior::Commit => {
-                    if self
-                        .state
-                        .preedit_result()
-                        .contains(InputResult::HAS_PREEDIT)
-                    {
-                        self.state.clear_preedit();
-                        ret |= InputResult::NEED_RESET;
-                        processed = true;
-                    }
+            }
+            HotkeyBehavior::Emoji => {
+                if self.emoji().unwrap_or(false) {
+                    ret |= InputResult::NEED_RESET;
+                    processed = true;
                 }
             }
+            HotkeyBehavior::Hanja => {
+                if self.open_candidates(config) {
+                    ret |= InputResult::HAS_CANDIDATE;
+                    processed = true;
+                } else if self.hanja().unwrap_or(false) {
+                    ret |= InputResult::NEED_RESET;
+                    processed = true;
+                }
 
-            match (hotkey.result(), processed) {
-                (HotkeyResult::Bypass, _) | (HotkeyResult::ConsumeIfProcessed, false) => {
-                    ret |= self.bypass();
+                if processed && self.delete_surrounding != 0 {
+                    ret |= InputResult::DELETE_SURROUNDING;
                 }
-                (HotkeyResult::Consume, _) | (HotkeyResult::ConsumeIfProcessed, true) => {
-                    ret |= InputResult::CONSUMED | self.state.preedit_result();
+            }
+            HotkeyBehavior::ToHangul => {
+                if !self.check_hangul_state(config) {
+                    self.enable_hangul = true;
+                    ret |= InputResult::LANGUAGE_CHANGED;
+                    processed = true;
+                }
+            }
+            HotkeyBehavior::ToggleHangul => {
+                self.enable_hangul = !self.check_hangul_state(config);
+                ret |= InputResult::LANGUAGE_CHANGED;
+                processed = true;
+            }
+            HotkeyBehavior::Commit => {
+                if self
+                    .state
+                    .preedit_result()
+                    .contains(InputResult::HAS_PREEDIT)
+                {
+                    self.state.clear_preedit();
+                    ret |= InputResult::NEED_RESET;
+                    processed = true;
+                }
+            }
+            HotkeyBehavior::BackspaceJamo => {
+                processed = self
+                    .state
+                    .backspace(BackspaceMode::Jamo, config)
+                    .contains(InputResult::CONSUMED);
+            }
+            HotkeyBehavior::BackspaceSyllable => {
+                processed = self
+                    .state
+                    .backspace(BackspaceMode::Syllable, config)
+                    .contains(InputResult::CONSUMED);
+            }
+            HotkeyBehavior::BackspaceWord => {
+                processed = self
+                    .state
+                    .backspace(BackspaceMode::Word, config)
+                    .contains(InputResult::CONSUMED);
+            }
+            HotkeyBehavior::Cancel => {
+                if self
+                    .state
+                    .preedit_result()
+                    .contains(InputResult::HAS_PREEDIT)
+                {
+                    self.state.discard_preedit();
+                    processed = true;
                 }
             }
+        }
 
-            ret
-        } else if key.code == KeyCode::Shift {
-            // Don't reset state
-            self.state.preedit_result()
-        } else if self.check_hangul_state(config) {
-            if key.code == KeyCode::Backspace {
-                self.state.backspace(config)
-            } else if let Some(v) = config.layout.keymap.get(&key) {
-                self.state.key(v, config)
-            } else {
-                self.bypass()
+        match (hotkey.result(), processed) {
+            (HotkeyResult::Bypass, _) | (HotkeyResult::ConsumeIfProcessed, false) => {
+                ret |= self.bypass();
+            }
+            (HotkeyResult::Consume, _) | (HotkeyResult::ConsumeIfProcessed, true) => {
+                ret |= InputResult::CONSUMED | self.state.preedit_result();
             }
-        } else {
-            self.bypass()
         }
+
+        ret
     }
 
     pub fn press_key_code(
@@ -226,7 +750,22 @@ impl InputEngine {
     ) -> InputResult {
         match KeyCode::from_hardward_code(hardware_code) {
             Some(code) => self.press_key(Key::new(code, state), config),
-            None => self.bypass(),
+            None => {
+                self.tap_key = None;
+                self.preedit_action(config.commit_policy().other)
+            }
+        }
+    }
+
+    pub fn release_key_code(
+        &mut self,
+        hardware_code: u16,
+        state: ModifierState,
+        config: &Config,
+    ) -> InputResult {
+        match KeyCode::from_hardward_code(hardware_code) {
+            Some(code) => self.release_key(Key::new(code, state), config),
+            None => InputResult::empty(),
         }
     }
 
@@ -235,9 +774,100 @@ impl InputEngine {
         self.state.clear_preedit();
     }
 
-    #[inline]
+    /// Apply `focus_out` of `CommitPolicy`
+    ///
+    /// Preedit is kept when result doesn't have `NEED_RESET`
+    pub fn focus_out(&mut self, config: &Config) -> InputResult {
+        self.tap_key = None;
+        self.candidates = None;
+        self.sequence.clear();
+
+        match config.commit_policy().focus_out {
+            PreeditAction::Commit => {
+                self.state.clear_preedit();
+                InputResult::NEED_RESET
+            }
+            PreeditAction::Discard => {
+                self.state.discard_preedit();
+                InputResult::NEED_RESET
+            }
+            PreeditAction::Keep => self.state.preedit_result(),
+        }
+    }
+
+    /// Cursor position of `preedit_str` in bytes
+    pub fn preedit_cursor(&mut self) -> usize {
+        if self.sequence.is_empty() {
+            self.state.preedit_cursor()
+        } else {
+            self.preedit_str().len()
+        }
+    }
+
+    /// Preedit string with hint of pending hotkey sequence
     pub fn preedit_str(&mut self) -> &str {
-        self.state.preedit_str()
+        if self.sequence.is_empty() {
+            return self.state.preedit_str();
+        }
+
+        self.preedit_buf.clear();
+        self.preedit_buf.push_str(self.state.preedit_str());
+
+        for key in self.sequence.iter() {
+            if !self.preedit_buf.is_empty() {
+                self.preedit_buf.push(' ');
+            }
+            write!(self.preedit_buf, "{}", key).ok();
+        }
+
+        &self.preedit_buf
+    }
+
+    /// Save hangul mode and preedit state
+    pub fn snapshot(&self) -> EngineSnapshot {
+        EngineSnapshot {
+            hangul: self.enable_hangul,
+            state: self.state.snapshot(),
+        }
+    }
+
+    /// Restore state saved by `snapshot`, pending hotkeys are dropped
+    pub fn restore(&mut self, snapshot: &EngineSnapshot) {
+        self.tap_key = None;
+        self.sequence.clear();
+        self.candidates = None;
+        self.enable_hangul = snapshot.hangul;
+        self.state.restore(&snapshot.state);
+    }
+
+    /// Load trailing hangul of text before cursor into preedit to edit it again
+    ///
+    /// Return count of characters frontend must delete before cursor, nothing is loaded when
+    /// preedit is not empty
+    pub fn reconvert(&mut self, before_cursor: &str, config: &Config) -> usize {
+        self.state.reconvert(before_cursor, config.word_boundary())
+    }
+
+    /// Jamo slots of syllable being composed
+    pub fn composition(&self) -> Composition {
+        self.state.composition()
+    }
+
+    /// Segments of `preedit_str` with style hints, ordered by offset
+    pub fn preedit_segments(&mut self) -> &[PreeditSegment] {
+        let len = self.preedit_str().len();
+
+        self.segments.clear();
+
+        if self.candidates.is_some() {
+            preedit::push_segment(&mut self.segments, 0, len, PreeditStyle::SelectedCandidate);
+            return &self.segments;
+        }
+
+        let end = self.state.preedit_segments(&mut self.segments);
+        preedit::push_segment(&mut self.segments, end, len, PreeditStyle::Hint);
+
+        &self.segments
     }
 
     #[inline]
@@ -252,6 +882,9 @@ impl InputEngine {
 
     #[inline]
     pub fn reset(&mut self) {
+        self.tap_key = None;
+        self.sequence.clear();
+        self.candidates = None;
         self.state.reset();
     }
 }
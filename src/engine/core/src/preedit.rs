@@ -0,0 +1,85 @@
+/// Style hint of preedit segment
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PreeditStyle {
+    /// Finished character in word of `word_commit` mode
+    Word,
+    /// Character being composed
+    Composing,
+    /// Keys of pending hotkey sequence
+    Hint,
+    /// Text which is replaced by selected candidate while candidate list is opened
+    SelectedCandidate,
+}
+
+/// Part of preedit string with same style
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PreeditSegment {
+    /// Byte offset of segment start in preedit string
+    pub start: usize,
+    /// Byte offset of segment end in preedit string
+    pub end: usize,
+    pub style: PreeditStyle,
+}
+
+impl PreeditSegment {
+    pub const fn new(start: usize, end: usize, style: PreeditStyle) -> Self {
+        Self { start, end, style }
+    }
+}
+
+/// Slot of syllable
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JamoSlot {
+    Choseong,
+    Jungseong,
+    Jongseong,
+}
+
+/// Read-only view of syllable being composed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Composition {
+    /// Compatibility jamo of choseong
+    pub cho: Option<char>,
+    /// Compatibility jamo of jungseong
+    pub jung: Option<char>,
+    /// Jungseong can be combined with next one
+    pub compose_jung: bool,
+    /// Compatibility jamo of jongseong
+    pub jong: Option<char>,
+}
+
+impl Composition {
+    /// Slot expected to be filled by next jamo
+    ///
+    /// `Choseong` after jongseong means next jamo usually starts new syllable
+    pub fn next_slot(&self) -> JamoSlot {
+        match (self.cho, self.jung, self.jong) {
+            (None, None, None) => JamoSlot::Choseong,
+            (Some(_), None, None) => JamoSlot::Jungseong,
+            (Some(_), Some(_), None) => JamoSlot::Jongseong,
+            _ => JamoSlot::Choseong,
+        }
+    }
+}
+
+/// Push segment when it's not empty, merge it with last one when it has same style
+///
+/// Return end of segment
+pub(crate) fn push_segment(
+    segments: &mut Vec<PreeditSegment>,
+    start: usize,
+    end: usize,
+    style: PreeditStyle,
+) -> usize {
+    if start < end {
+        match segments.last_mut() {
+            Some(last) if last.style == style && last.end == start => last.end = end,
+            _ => segments.push(PreeditSegment::new(start, end, style)),
+        }
+    }
+
+    end
+}
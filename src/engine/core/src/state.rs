@@ -1,6 +1,9 @@
+use serde::{Deserialize, Serialize};
+
 use crate::{
     characters::{Choseong, JongToCho, Jongseong, Jungseong, KeyValue},
-    config::Addon,
+    config::{Addon, BackspaceMode, WordBoundary},
+    preedit::{push_segment, Composition, PreeditSegment, PreeditStyle},
     Config, InputResult,
 };
 
@@ -10,7 +13,11 @@ pub struct HangulState {
     state: CharacterState,
     word_commit: bool,
     commit_buf: String,
-    commit_characters: Vec<CharacterState>,
+    commit_characters: Vec<WordCharacter>,
+    /// Characters after cursor in reversed order, only used in word_commit
+    after_cursor: Vec<WordCharacter>,
+    /// Word reached `max_length` and only characters before composing one will be committed
+    flush_word: bool,
     buf: String,
 }
 
@@ -21,31 +28,219 @@ impl HangulState {
             word_commit,
             commit_buf: String::with_capacity(64),
             commit_characters: Vec::with_capacity(16),
+            after_cursor: Vec::with_capacity(16),
+            flush_word: false,
             buf: String::with_capacity(64),
         }
     }
 
+    pub fn word_commit(&self) -> bool {
+        self.word_commit
+    }
+
     pub fn commit_str(&mut self) -> &str {
-        if self.word_commit {
+        if self.word_commit && !self.flush_word {
             self.buf.clear();
             self.buf.push_str(&self.commit_buf);
-            self.state.write(&mut self.buf);
+            self.write_composing();
             &self.buf
         } else {
             &self.commit_buf
         }
     }
 
+    /// Write composing character and characters after cursor
+    fn write_composing(&mut self) {
+        self.state.write(&mut self.buf);
+        for c in self.after_cursor.iter().rev() {
+            c.write(&mut self.buf);
+        }
+    }
+
+    /// Cursor position of preedit_str in bytes
+    pub fn preedit_cursor(&self) -> usize {
+        let composing = if self.state.need_display() {
+            self.state.to_char().len_utf8()
+        } else {
+            0
+        };
+
+        if self.word_commit && !self.flush_word {
+            self.commit_buf.len() + composing
+        } else {
+            composing
+        }
+    }
+
+    pub fn composition(&self) -> Composition {
+        self.state.composition()
+    }
+
+    pub fn snapshot(&self) -> HangulSnapshot {
+        HangulSnapshot {
+            state: self.state,
+            word: self.commit_characters.clone(),
+            after_cursor: self.after_cursor.clone(),
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &HangulSnapshot) {
+        self.reset();
+        self.state = snapshot.state;
+        self.commit_characters.extend_from_slice(&snapshot.word);
+        self.commit_buf
+            .extend(self.commit_characters.iter().map(|c| c.to_char()));
+        self.after_cursor.extend_from_slice(&snapshot.after_cursor);
+    }
+
+    /// Load trailing hangul of `before_cursor` into preedit, only last syllable is loaded when
+    /// not in word_commit mode
+    ///
+    /// Return count of loaded characters
+    pub fn reconvert(&mut self, before_cursor: &str, boundary: WordBoundary) -> usize {
+        if self.preedit_result().contains(InputResult::HAS_PREEDIT) {
+            return 0;
+        }
+
+        let max = if !self.word_commit {
+            1
+        } else if boundary.max_length != 0 {
+            boundary.max_length
+        } else {
+            usize::MAX
+        };
+
+        let mut chars = Vec::new();
+
+        for ch in before_cursor.chars().rev().take(max) {
+            match CharacterState::from_char(ch) {
+                Some(c) => chars.push(c),
+                None => break,
+            }
+        }
+
+        let count = chars.len();
+
+        let last = match chars.first().copied() {
+            Some(last) => last,
+            None => return 0,
+        };
+
+        self.reset();
+        self.state = last;
+
+        for c in chars.drain(1..).rev() {
+            self.commit_buf.push(c.to_char());
+            self.commit_characters.push(WordCharacter::Hangul(c));
+        }
+
+        count
+    }
+
+    /// Push segments of preedit_str, return end of them
+    pub fn preedit_segments(&self, out: &mut Vec<PreeditSegment>) -> usize {
+        let word = if self.word_commit && !self.flush_word {
+            self.commit_buf.len()
+        } else {
+            0
+        };
+        let after: usize = self
+            .after_cursor
+            .iter()
+            .map(|c| c.to_char().len_utf8())
+            .sum();
+
+        let end = push_segment(out, 0, word, PreeditStyle::Word);
+        let end = push_segment(out, end, self.preedit_cursor(), PreeditStyle::Composing);
+        push_segment(out, end, end + after, PreeditStyle::Word)
+    }
+
+    /// Move composing character into word
+    fn finish_character(&mut self) {
+        if self.state.need_display() {
+            self.commit_buf.push(self.state.to_char());
+            self.commit_characters
+                .push(WordCharacter::Hangul(self.state));
+        }
+
+        self.state.reset();
+    }
+
+    /// Move cursor to left in word, return `false` when cursor is already at start
+    pub fn cursor_left(&mut self) -> bool {
+        if !self.word_commit {
+            return false;
+        }
+
+        self.finish_character();
+
+        match self.commit_characters.pop() {
+            Some(c) => {
+                self.commit_buf.pop();
+                self.after_cursor.push(c);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Move cursor to right in word, return `false` when cursor is already at end
+    pub fn cursor_right(&mut self) -> bool {
+        if !self.word_commit {
+            return false;
+        }
+
+        self.finish_character();
+
+        match self.after_cursor.pop() {
+            Some(c) => {
+                self.commit_buf.push(c.to_char());
+                self.commit_characters.push(c);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Delete character after cursor in word
+    pub fn delete(&mut self) -> bool {
+        self.after_cursor.pop().is_some()
+    }
+
     pub fn preedit_str(&mut self) -> &str {
-        if self.word_commit {
+        if self.word_commit && !self.flush_word {
             self.commit_str()
         } else {
             self.buf.clear();
-            self.state.write(&mut self.buf);
+            self.write_composing();
             &self.buf
         }
     }
 
+    /// Add characters which don't end word to word
+    pub fn insert_word(&mut self, s: &str, boundary: WordBoundary) -> InputResult {
+        self.finish_character();
+
+        for c in s.chars() {
+            self.commit_buf.push(c);
+            self.commit_characters.push(WordCharacter::Other(c));
+        }
+
+        self.check_word_length(boundary) | self.preedit_result() | InputResult::CONSUMED
+    }
+
+    /// Flush characters before cursor when word reached `max_length`
+    fn check_word_length(&mut self, boundary: WordBoundary) -> InputResult {
+        if boundary.max_length != 0
+            && self.commit_characters.len() + self.after_cursor.len() >= boundary.max_length
+        {
+            self.flush_word = true;
+            InputResult::NEED_FLUSH
+        } else {
+            InputResult::empty()
+        }
+    }
+
     pub fn pass(&mut self, s: &str) {
         self.commit_characters.clear();
         self.clear_preedit();
@@ -57,6 +252,7 @@ impl HangulState {
 
         if self.word_commit {
             self.commit_buf.clear();
+            self.after_cursor.clear();
         }
 
         self.state.reset();
@@ -68,34 +264,55 @@ impl HangulState {
         self.state.reset();
     }
 
+    /// Throw away preedit without commit
+    pub fn discard_preedit(&mut self) {
+        if self.word_commit {
+            self.commit_characters.clear();
+            self.after_cursor.clear();
+            self.commit_buf.clear();
+        }
+
+        self.state.reset();
+    }
+
     pub fn flush(&mut self) {
         self.commit_characters.clear();
         self.commit_buf.clear();
+        self.flush_word = false;
     }
 
     pub fn reset(&mut self) {
         self.flush();
+        self.after_cursor.clear();
         self.state.reset();
     }
 
     pub fn preedit_result(&self) -> InputResult {
-        if self.state.need_display() || self.word_commit && !self.commit_characters.is_empty() {
+        if self.state.need_display()
+            || self.word_commit
+                && !(self.commit_characters.is_empty() && self.after_cursor.is_empty())
+        {
             InputResult::HAS_PREEDIT
         } else {
             InputResult::empty()
         }
     }
 
-    fn convert_result(&mut self, ret: CharacterResult) -> InputResult {
+    fn convert_result(&mut self, ret: CharacterResult, boundary: WordBoundary) -> InputResult {
         match ret {
             CharacterResult::Consume => self.preedit_result() | InputResult::CONSUMED,
             CharacterResult::NewCharacter(new) => {
                 self.commit_buf.push(self.state.to_char());
                 self.commit_characters
-                    .push(std::mem::replace(&mut self.state, new));
+                    .push(WordCharacter::Hangul(std::mem::replace(
+                        &mut self.state,
+                        new,
+                    )));
 
                 if self.word_commit {
-                    InputResult::HAS_PREEDIT | InputResult::CONSUMED
+                    self.check_word_length(boundary)
+                        | InputResult::HAS_PREEDIT
+                        | InputResult::CONSUMED
                 } else {
                     InputResult::NEED_FLUSH | self.preedit_result() | InputResult::CONSUMED
                 }
@@ -103,27 +320,77 @@ impl HangulState {
         }
     }
 
-    pub fn backspace(&mut self, config: &Config) -> InputResult {
+    pub fn backspace(&mut self, mode: BackspaceMode, config: &Config) -> InputResult {
+        match mode {
+            BackspaceMode::Jamo => self.backspace_jamo(config),
+            BackspaceMode::Syllable => {
+                if !self.state.is_empty() {
+                    self.state.reset();
+                } else if self.commit_characters.pop().is_some() {
+                    self.commit_buf.pop();
+                } else {
+                    return self.backspace_at_start();
+                }
+
+                self.preedit_result() | InputResult::CONSUMED
+            }
+            BackspaceMode::Word => {
+                if self.state.is_empty() && self.commit_characters.is_empty() {
+                    return self.backspace_at_start();
+                }
+
+                // Keep word after cursor
+                if self.word_commit {
+                    self.commit_characters.clear();
+                    self.commit_buf.clear();
+                }
+
+                self.state.reset();
+                self.preedit_result() | InputResult::CONSUMED
+            }
+        }
+    }
+
+    fn backspace_jamo(&mut self, config: &Config) -> InputResult {
         loop {
             if self.state.backspace(config) {
                 return self.preedit_result() | InputResult::CONSUMED;
             }
 
             match self.commit_characters.pop() {
-                Some(new_last) => {
+                Some(WordCharacter::Hangul(new_last)) => {
                     self.commit_buf.pop();
                     self.state = new_last;
                 }
+                Some(WordCharacter::Other(_)) => {
+                    self.commit_buf.pop();
+                    return self.preedit_result() | InputResult::CONSUMED;
+                }
                 None => {
-                    return InputResult::empty();
+                    return self.backspace_at_start();
                 }
             }
         }
     }
 
+    /// Nothing is before cursor, keep word after cursor instead of passing Backspace to client
+    fn backspace_at_start(&self) -> InputResult {
+        if self.after_cursor.is_empty() {
+            InputResult::empty()
+        } else {
+            self.preedit_result() | InputResult::CONSUMED
+        }
+    }
+
     pub fn key(&mut self, kv: &KeyValue, config: &Config) -> InputResult {
         let ret = match kv {
             KeyValue::Pass(pass) => {
+                let boundary = config.word_boundary();
+
+                if self.word_commit && !boundary.is_boundary(pass) {
+                    return self.insert_word(pass, boundary);
+                }
+
                 self.pass(pass);
                 return InputResult::NEED_RESET | InputResult::CONSUMED;
             }
@@ -147,7 +414,39 @@ impl HangulState {
             } => self.state.jung_jong(*jung, *jong, *first, *compose, config),
         };
 
-        self.convert_result(ret)
+        self.convert_result(ret, config.word_boundary())
+    }
+}
+
+/// Saved composing character and word
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HangulSnapshot {
+    state: CharacterState,
+    word: Vec<WordCharacter>,
+    after_cursor: Vec<WordCharacter>,
+}
+
+/// Character in word of `word_commit` mode
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+enum WordCharacter {
+    Hangul(CharacterState),
+    /// Punctuation or digit which doesn't end word
+    Other(char),
+}
+
+impl WordCharacter {
+    fn to_char(self) -> char {
+        match self {
+            Self::Hangul(c) => c.to_char(),
+            Self::Other(c) => c,
+        }
+    }
+
+    fn write(self, out: &mut String) {
+        match self {
+            Self::Hangul(c) => c.write(out),
+            Self::Other(c) => out.push(c),
+        }
     }
 }
 
@@ -158,7 +457,7 @@ enum CharacterResult {
 }
 
 /// 한글 글자 상태
-#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 struct CharacterState {
     cho: Option<Choseong>,
     jung: Option<Jungseong>,
@@ -183,6 +482,42 @@ impl CharacterState {
         self.jong = None;
     }
 
+    pub const fn is_empty(&self) -> bool {
+        matches!((self.cho, self.jung, self.jong), (None, None, None))
+    }
+
+    /// Decompose hangul syllable or compatibility jamo
+    pub fn from_char(ch: char) -> Option<Self> {
+        if let Some((cho, jung, jong)) = Choseong::decompose(ch) {
+            Some(Self {
+                cho: Some(cho),
+                jung: Some(jung),
+                compose_jung: true,
+                jong,
+            })
+        } else if let Some(cho) = Choseong::from_jamo(ch) {
+            Some(Self {
+                cho: Some(cho),
+                ..Self::new()
+            })
+        } else {
+            Jungseong::from_jamo(ch).map(|jung| Self {
+                jung: Some(jung),
+                compose_jung: true,
+                ..Self::new()
+            })
+        }
+    }
+
+    pub fn composition(&self) -> Composition {
+        Composition {
+            cho: self.cho.map(Choseong::jamo),
+            jung: self.jung.map(Jungseong::jamo),
+            compose_jung: self.jung.is_some() && self.compose_jung,
+            jong: self.jong.map(Jongseong::jamo),
+        }
+    }
+
     pub fn to_char(&self) -> char {
         match (self.cho, self.jung, self.jong) {
             (None, None, None) |
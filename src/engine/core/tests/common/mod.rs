@@ -0,0 +1,60 @@
+use kime_engine_core::{
+    BackspaceMode, Config, InputEngine, InputResult, Key, RawConfig, WordBoundary,
+};
+
+pub fn backspace_config(layout: &str, backspace_mode: BackspaceMode) -> Config {
+    Config::from_raw_config(
+        RawConfig {
+            layout: layout.into(),
+            backspace_mode,
+            ..Default::default()
+        },
+        None,
+    )
+}
+
+pub fn word_boundary_config(layout: &str, word_boundary: WordBoundary) -> Config {
+    Config::from_raw_config(
+        RawConfig {
+            layout: layout.into(),
+            word_boundary,
+            ..Default::default()
+        },
+        None,
+    )
+}
+
+#[track_caller]
+pub fn test_input_impl(config: &Config, word_commit: bool, keys: &[(Key, &str, &str)]) {
+    let mut engine = InputEngine::new(word_commit);
+
+    engine.set_hangul_enable(true);
+
+    for (key, preedit, commit) in keys.iter().copied() {
+        eprintln!("Key: {:?}", key);
+
+        let ret = engine.press_key(key, config);
+
+        eprintln!("Ret: {:?}", ret);
+
+        if ret.contains(InputResult::HAS_PREEDIT) {
+            assert_eq!(preedit, engine.preedit_str());
+        } else {
+            assert!(preedit.is_empty());
+        }
+
+        if !ret.contains(InputResult::CONSUMED) {
+            assert_eq!(commit, format!("{}PASS", engine.commit_str()));
+        } else if ret.intersects(InputResult::NEED_RESET | InputResult::NEED_FLUSH) {
+            assert_eq!(commit, engine.commit_str());
+        } else {
+            assert!(commit.is_empty());
+        }
+
+        if ret.contains(InputResult::NEED_RESET) {
+            engine.reset();
+        } else if ret.contains(InputResult::NEED_FLUSH) {
+            engine.flush();
+        }
+    }
+}
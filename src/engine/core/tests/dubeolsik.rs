@@ -1,8 +1,15 @@
+mod common;
+
 use std::collections::BTreeMap;
+use std::time::Duration;
 
+use common::{backspace_config, test_input_impl, word_boundary_config};
 use enumset::EnumSet;
 use kime_engine_core::{
-    Addon, Config, Hotkey, InputEngine, InputResult, Key, KeyCode::*, RawConfig,
+    Addon, BackspaceMode, CommitPolicy, Config, EngineSnapshot, HanjaDict, Hotkey, HotkeyBehavior,
+    HotkeyCondition, HotkeyList, HotkeyResult, HotkeyTrigger, InputEngine, InputMode, InputPurpose,
+    InputResult, JamoSlot, Key, KeyCode::*, KeySequence, Layout, ModifierState, PreeditAction,
+    PreeditSegment, PreeditStyle, RawConfig, SpaceAction, WordBoundary,
 };
 
 fn default_config() -> Config {
@@ -26,7 +33,7 @@ fn addon_config(addon: EnumSet<Addon>) -> Config {
     )
 }
 
-fn hotkey_config(hotkeys: BTreeMap<Key, Hotkey>) -> Config {
+fn hotkey_config(hotkeys: BTreeMap<Key, HotkeyList>) -> Config {
     Config::from_raw_config(
         RawConfig {
             layout: "dubeolsik".into(),
@@ -37,41 +44,6 @@ fn hotkey_config(hotkeys: BTreeMap<Key, Hotkey>) -> Config {
     )
 }
 
-#[track_caller]
-fn test_input_impl(config: &Config, word_commit: bool, keys: &[(Key, &str, &str)]) {
-    let mut engine = InputEngine::new(word_commit);
-
-    engine.set_hangul_enable(true);
-
-    for (key, preedit, commit) in keys.iter().copied() {
-        eprintln!("Key: {:?}", key);
-
-        let ret = engine.press_key(key, &config);
-
-        eprintln!("Ret: {:?}", ret);
-
-        if ret.contains(InputResult::HAS_PREEDIT) {
-            assert_eq!(preedit, engine.preedit_str());
-        } else {
-            assert!(preedit.is_empty());
-        }
-
-        if !ret.contains(InputResult::CONSUMED) {
-            assert_eq!(commit, format!("{}PASS", engine.commit_str()));
-        } else if ret.intersects(InputResult::NEED_RESET | InputResult::NEED_FLUSH) {
-            assert_eq!(commit, engine.commit_str());
-        } else {
-            assert!(commit.is_empty());
-        }
-
-        if ret.contains(InputResult::NEED_RESET) {
-            engine.reset();
-        } else if ret.contains(InputResult::NEED_FLUSH) {
-            engine.flush();
-        }
-    }
-}
-
 #[track_caller]
 fn test_input(keys: &[(Key, &str, &str)]) {
     test_input_impl(&default_config(), false, keys);
@@ -83,7 +55,7 @@ fn test_input_with_addon(keys: &[(Key, &str, &str)], addon: EnumSet<Addon>) {
 }
 
 #[track_caller]
-fn test_input_with_hotkey(keys: &[(Key, &str, &str)], hotkeys: BTreeMap<Key, Hotkey>) {
+fn test_input_with_hotkey(keys: &[(Key, &str, &str)], hotkeys: BTreeMap<Key, HotkeyList>) {
     test_input_impl(&hotkey_config(hotkeys), false, keys);
 }
 
@@ -114,15 +86,350 @@ fn space_commit() {
         ],
         std::iter::once((
             Key::normal(Space),
-            Hotkey::new(
-                kime_engine_core::HotkeyBehavior::Commit,
-                kime_engine_core::HotkeyResult::ConsumeIfProcessed,
-            ),
+            Hotkey::new(HotkeyBehavior::Commit, HotkeyResult::ConsumeIfProcessed).into(),
+        ))
+        .collect(),
+    )
+}
+
+fn tap_config(tap_hotkey_timeout: u64) -> Config {
+    Config::from_raw_config(
+        RawConfig {
+            layout: "dubeolsik".into(),
+            hotkeys: std::iter::once((
+                Key::normal(ShiftL),
+                Hotkey::new(HotkeyBehavior::ToggleHangul, HotkeyResult::Consume)
+                    .with_trigger(HotkeyTrigger::Tap)
+                    .into(),
+            ))
+            .collect(),
+            tap_hotkey_timeout,
+            ..Default::default()
+        },
+        None,
+    )
+}
+
+#[test]
+fn tap_hotkey() {
+    let config = tap_config(300);
+    let mut engine = InputEngine::new(false);
+    engine.set_hangul_enable(true);
+
+    assert_eq!(
+        engine.press_key(Key::normal(R), &config),
+        InputResult::CONSUMED | InputResult::HAS_PREEDIT
+    );
+    assert_eq!(
+        engine.press_key(Key::normal(ShiftL), &config),
+        InputResult::HAS_PREEDIT
+    );
+    assert_eq!(
+        engine.release_key(Key::shift(ShiftL), &config),
+        InputResult::LANGUAGE_CHANGED | InputResult::HAS_PREEDIT
+    );
+    assert!(!engine.is_hangul_enabled());
+    assert_eq!(engine.preedit_str(), "ㄱ");
+
+    // Used as modifier
+    engine.press_key(Key::normal(ShiftL), &config);
+    engine.press_key(Key::shift(R), &config);
+    assert_eq!(
+        engine.release_key(Key::shift(ShiftL), &config),
+        InputResult::empty()
+    );
+    assert!(!engine.is_hangul_enabled());
+}
+
+#[test]
+fn tap_hotkey_timeout() {
+    let config = tap_config(0);
+    let mut engine = InputEngine::new(false);
+    engine.set_hangul_enable(true);
+
+    engine.press_key(Key::normal(ShiftL), &config);
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    assert_eq!(
+        engine.release_key(Key::shift(ShiftL), &config),
+        InputResult::empty()
+    );
+    assert!(engine.is_hangul_enabled());
+}
+
+fn sequence_config(sequence_hotkey_timeout: u64) -> Config {
+    Config::from_raw_config(
+        RawConfig {
+            layout: "dubeolsik".into(),
+            sequence_hotkeys: vec![
+                (
+                    KeySequence(vec![Key::ctrl(SemiColon), Key::normal(E)]),
+                    Hotkey::new(HotkeyBehavior::ToEnglish, HotkeyResult::Consume),
+                ),
+                (
+                    KeySequence(vec![Key::ctrl(SemiColon), Key::normal(H)]),
+                    Hotkey::new(HotkeyBehavior::ToHangul, HotkeyResult::Consume),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+            sequence_hotkey_timeout,
+            ..Default::default()
+        },
+        None,
+    )
+}
+
+#[test]
+fn sequence_hotkey() {
+    let config = sequence_config(1000);
+    let mut engine = InputEngine::new(false);
+    engine.set_hangul_enable(true);
+
+    engine.press_key(Key::normal(R), &config);
+    assert_eq!(
+        engine.press_key(Key::ctrl(SemiColon), &config),
+        InputResult::CONSUMED | InputResult::SEQUENCE_PENDING | InputResult::HAS_PREEDIT
+    );
+    assert_eq!(engine.preedit_str(), "ㄱ C-SemiColon");
+    // Modifier keys don't break sequence
+    assert!(engine
+        .press_key(Key::ctrl(ControlL), &config)
+        .contains(InputResult::SEQUENCE_PENDING));
+    assert_eq!(
+        engine.press_key(Key::normal(E), &config),
+        InputResult::CONSUMED | InputResult::LANGUAGE_CHANGED | InputResult::HAS_PREEDIT
+    );
+    assert!(!engine.is_hangul_enabled());
+    assert_eq!(engine.preedit_str(), "ㄱ");
+}
+
+#[test]
+fn sequence_hotkey_unmatched() {
+    test_input_impl(
+        &sequence_config(1000),
+        false,
+        &[
+            (Key::normal(R), "ㄱ", ""),
+            (Key::ctrl(SemiColon), "ㄱ C-SemiColon", ""),
+            (Key::normal(K), "ㅏ", "ㄱ"),
+            (Key::ctrl(SemiColon), "ㅏ C-SemiColon", ""),
+            (Key::normal(Esc), "", "ㅏPASS"),
+        ],
+    );
+}
+
+#[test]
+fn sequence_hotkey_timeout() {
+    let config = sequence_config(0);
+    let mut engine = InputEngine::new(false);
+    engine.set_hangul_enable(true);
+
+    engine.press_key(Key::ctrl(SemiColon), &config);
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    let ret = engine.press_key(Key::normal(E), &config);
+    assert!(ret.contains(InputResult::REPLAY_SEQUENCE));
+    assert!(!ret.contains(InputResult::SEQUENCE_PENDING));
+    assert!(engine.is_hangul_enabled());
+    assert_eq!(engine.preedit_str(), "ㄷ");
+}
+
+#[test]
+fn sequence_hotkey_expire() {
+    let mut engine = InputEngine::new(false);
+    engine.set_hangul_enable(true);
+
+    let config = sequence_config(1000);
+    engine.press_key(Key::normal(R), &config);
+    engine.press_key(Key::ctrl(SemiColon), &config);
+    assert!(engine.sequence_timeout(&config).unwrap() > Duration::from_millis(0));
+    assert_eq!(engine.expire_sequence(&config), InputResult::empty());
+    assert_eq!(engine.preedit_str(), "ㄱ C-SemiColon");
+
+    let config = sequence_config(0);
+    assert_eq!(
+        engine.sequence_timeout(&config),
+        Some(Duration::from_millis(0))
+    );
+    assert_eq!(
+        engine.expire_sequence(&config),
+        InputResult::REPLAY_SEQUENCE | InputResult::NEED_FLUSH
+    );
+    assert_eq!(engine.commit_str(), "ㄱ");
+    assert_eq!(engine.preedit_str(), "");
+    assert_eq!(engine.sequence_timeout(&config), None);
+}
+
+#[test]
+fn mode_hotkey() {
+    let config = hotkey_config(
+        std::iter::once((
+            Key::normal(AltR),
+            HotkeyList::Many(vec![
+                Hotkey::new(HotkeyBehavior::ToEnglish, HotkeyResult::Consume).with_condition(
+                    HotkeyCondition {
+                        mode: Some(InputMode::Hangul),
+                        ..Default::default()
+                    },
+                ),
+                Hotkey::new(HotkeyBehavior::ToHangul, HotkeyResult::Bypass).with_condition(
+                    HotkeyCondition {
+                        mode: Some(InputMode::English),
+                        ..Default::default()
+                    },
+                ),
+            ]),
+        ))
+        .collect(),
+    );
+    let mut engine = InputEngine::new(false);
+    engine.set_hangul_enable(true);
+
+    assert_eq!(
+        engine.press_key(Key::normal(AltR), &config),
+        InputResult::CONSUMED | InputResult::LANGUAGE_CHANGED
+    );
+    assert!(!engine.is_hangul_enabled());
+    assert_eq!(
+        engine.press_key(Key::normal(AltR), &config),
+        InputResult::NEED_RESET | InputResult::LANGUAGE_CHANGED
+    );
+    assert!(engine.is_hangul_enabled());
+}
+
+#[test]
+fn preedit_hotkey() {
+    test_input_with_hotkey(
+        &[
+            (Key::normal(Space), "", "PASS"),
+            (Key::normal(R), "ㄱ", ""),
+            (Key::normal(Space), "", "ㄱ"),
+        ],
+        std::iter::once((
+            Key::normal(Space),
+            Hotkey::new(HotkeyBehavior::Commit, HotkeyResult::Consume)
+                .with_condition(HotkeyCondition {
+                    preedit: Some(true),
+                    ..Default::default()
+                })
+                .into(),
+        ))
+        .collect(),
+    )
+}
+
+#[test]
+fn app_layout_hotkey() {
+    let config = hotkey_config(
+        std::iter::once((
+            Key::normal(AltR),
+            Hotkey::new(HotkeyBehavior::ToggleHangul, HotkeyResult::Consume)
+                .with_condition(HotkeyCondition {
+                    layout: Some("dubeolsik".into()),
+                    apps: vec!["kitty".into()],
+                    ..Default::default()
+                })
+                .into(),
+        ))
+        .collect(),
+    );
+    let mut engine = InputEngine::new(false);
+    engine.set_hangul_enable(true);
+
+    engine.set_app("gedit");
+    assert_eq!(
+        engine.press_key(Key::normal(AltR), &config),
+        InputResult::NEED_RESET
+    );
+    assert!(engine.is_hangul_enabled());
+
+    engine.set_app("kitty");
+    assert_eq!(
+        engine.press_key(Key::normal(AltR), &config),
+        InputResult::CONSUMED | InputResult::LANGUAGE_CHANGED
+    );
+    assert!(!engine.is_hangul_enabled());
+}
+
+#[test]
+fn cancel_hotkey() {
+    test_input_with_hotkey(
+        &[
+            (Key::normal(R), "ㄱ", ""),
+            (Key::normal(K), "가", ""),
+            (Key::normal(Esc), "", ""),
+            (Key::normal(Esc), "", "PASS"),
+        ],
+        std::iter::once((
+            Key::normal(Esc),
+            Hotkey::new(HotkeyBehavior::Cancel, HotkeyResult::ConsumeIfProcessed).into(),
         ))
         .collect(),
     )
 }
 
+fn policy_config(commit_policy: CommitPolicy) -> Config {
+    Config::from_raw_config(
+        RawConfig {
+            layout: "dubeolsik".into(),
+            commit_policy,
+            ..Default::default()
+        },
+        None,
+    )
+}
+
+#[test]
+fn commit_policy() {
+    let config = policy_config(CommitPolicy {
+        navigation: PreeditAction::Keep,
+        modifier: PreeditAction::Keep,
+        shortcut: PreeditAction::Discard,
+        ..Default::default()
+    });
+
+    test_input_impl(
+        &config,
+        false,
+        &[
+            (Key::normal(R), "ㄱ", ""),
+            (Key::normal(Left), "ㄱ", "PASS"),
+            (Key::normal(ControlL), "ㄱ", "PASS"),
+            (Key::ctrl(C), "", "PASS"),
+            (Key::normal(R), "ㄱ", ""),
+            (Key::normal(Esc), "", "ㄱPASS"),
+        ],
+    );
+}
+
+#[test]
+fn commit_policy_focus_out() {
+    let mut engine = InputEngine::new(false);
+    engine.set_hangul_enable(true);
+
+    let config = policy_config(CommitPolicy::default());
+    engine.press_key(Key::normal(R), &config);
+    assert_eq!(engine.focus_out(&config), InputResult::NEED_RESET);
+    assert_eq!(engine.commit_str(), "ㄱ");
+    engine.reset();
+
+    let config = policy_config(CommitPolicy {
+        focus_out: PreeditAction::Discard,
+        ..Default::default()
+    });
+    engine.press_key(Key::normal(R), &config);
+    assert_eq!(engine.focus_out(&config), InputResult::NEED_RESET);
+    assert_eq!(engine.commit_str(), "");
+    engine.reset();
+
+    let config = policy_config(CommitPolicy {
+        focus_out: PreeditAction::Keep,
+        ..Default::default()
+    });
+    engine.press_key(Key::normal(R), &config);
+    assert_eq!(engine.focus_out(&config), InputResult::HAS_PREEDIT);
+    assert_eq!(engine.preedit_str(), "ㄱ");
+}
+
 #[test]
 fn word_hello() {
     test_word_input(&[
@@ -136,6 +443,53 @@ fn word_hello() {
     ])
 }
 
+#[test]
+fn word_cursor() {
+    let config = default_config();
+    let mut engine = InputEngine::new(true);
+    engine.set_hangul_enable(true);
+
+    for code in [D, K, S, S, U, D].iter().copied() {
+        engine.press_key(Key::normal(code), &config);
+    }
+    assert_eq!(engine.preedit_str(), "안녕");
+    assert_eq!(engine.preedit_cursor(), "안녕".len());
+
+    assert_eq!(
+        engine.press_key(Key::normal(Left), &config),
+        InputResult::CONSUMED | InputResult::HAS_PREEDIT
+    );
+    assert_eq!(engine.preedit_cursor(), "안".len());
+
+    engine.press_key(Key::normal(Home), &config);
+    assert_eq!(engine.preedit_cursor(), 0);
+    // Already at start
+    assert!(!engine
+        .press_key(Key::normal(Left), &config)
+        .contains(InputResult::CONSUMED));
+}
+
+#[test]
+fn word_cursor_edit() {
+    test_word_input(&[
+        (Key::normal(D), "ㅇ", ""),
+        (Key::normal(K), "아", ""),
+        (Key::normal(S), "안", ""),
+        (Key::normal(S), "안ㄴ", ""),
+        (Key::normal(U), "안녀", ""),
+        (Key::normal(D), "안녕", ""),
+        (Key::normal(Home), "안녕", ""),
+        (Key::normal(R), "ㄱ안녕", ""),
+        (Key::normal(K), "가안녕", ""),
+        (Key::normal(Right), "가안녕", ""),
+        (Key::normal(Delete), "가안", ""),
+        (Key::normal(Backspace), "가아", ""),
+        (Key::normal(Left), "가아", ""),
+        (Key::normal(End), "가아", ""),
+        (Key::normal(Esc), "", "가아PASS"),
+    ]);
+}
+
 // issue #310
 #[test]
 fn hangul_change_preedit() {
@@ -217,6 +571,87 @@ fn number() {
     ]);
 }
 
+#[test]
+fn keypad() {
+    test_input(&[(Key::normal(R), "ㄱ", ""), (Key::normal(Kp1), "", "ㄱPASS")]);
+}
+
+#[test]
+fn keypad_layout() {
+    let layout = Layout::load_from("R: ㄱ$ㄱ\nK: ㅏ\nKpPlus: +\nS-Kp1: '!'").unwrap();
+    let config = Config::new(layout, RawConfig::default());
+
+    test_input_impl(
+        &config,
+        false,
+        &[
+            (Key::normal(R), "ㄱ", ""),
+            (Key::normal(K), "가", ""),
+            (Key::normal(KpPlus), "", "가+"),
+            (Key::shift(Kp1), "", "!"),
+        ],
+    );
+}
+
+#[test]
+fn keypad_numlock_layout() {
+    let layout = Layout::load_from("R: ㄱ$ㄱ\nNum-Kp1: 1").unwrap();
+    let config = Config::new(layout, RawConfig::default());
+
+    test_input_impl(
+        &config,
+        false,
+        &[
+            (Key::normal(R), "ㄱ", ""),
+            (Key::new(Kp1, ModifierState::NUMLOCK), "", "ㄱ1"),
+            (Key::normal(R), "ㄱ", ""),
+            (Key::normal(Kp1), "", "ㄱPASS"),
+        ],
+    );
+}
+
+#[test]
+fn caps_lock() {
+    test_input(&[
+        (Key::new(R, ModifierState::CAPSLOCK), "ㄱ", ""),
+        (Key::new(K, ModifierState::CAPSLOCK), "가", ""),
+        (
+            Key::new(Q, ModifierState::CAPSLOCK | ModifierState::SHIFT),
+            "ㅃ",
+            "가",
+        ),
+    ]);
+}
+
+#[test]
+fn caps_lock_hotkey() {
+    let config = Config::from_raw_config(
+        RawConfig {
+            layout: "dubeolsik".into(),
+            ignore_caps_lock: true,
+            hotkeys: std::iter::once((
+                Key::normal(CapsLock),
+                Hotkey::new(HotkeyBehavior::ToggleHangul, HotkeyResult::Consume).into(),
+            ))
+            .collect(),
+            ..Default::default()
+        },
+        None,
+    );
+
+    test_input_impl(
+        &config,
+        false,
+        &[
+            (Key::normal(R), "ㄱ", ""),
+            (Key::normal(CapsLock), "ㄱ", ""),
+            (Key::new(R, ModifierState::CAPSLOCK), "", "ㄱPASS"),
+            (Key::new(CapsLock, ModifierState::CAPSLOCK), "", ""),
+            (Key::new(R, ModifierState::CAPSLOCK), "ㄱ", ""),
+        ],
+    );
+}
+
 #[test]
 fn exclamation_mark() {
     test_input(&[(Key::shift(R), "ㄲ", ""), (Key::shift(One), "", "ㄲ!")]);
@@ -271,3 +706,526 @@ fn backspace_moum_compose() {
         (Key::normal(Backspace), "ㅇ", ""),
     ])
 }
+
+#[test]
+fn backspace_syllable() {
+    test_input_impl(
+        &backspace_config("dubeolsik", BackspaceMode::Syllable),
+        false,
+        &[
+            (Key::normal(D), "ㅇ", ""),
+            (Key::normal(K), "아", ""),
+            (Key::normal(S), "안", ""),
+            (Key::normal(Backspace), "", ""),
+            (Key::normal(Backspace), "", "PASS"),
+        ],
+    );
+}
+
+#[test]
+fn backspace_syllable_word_commit() {
+    test_input_impl(
+        &backspace_config("dubeolsik", BackspaceMode::Syllable),
+        true,
+        &[
+            (Key::normal(D), "ㅇ", ""),
+            (Key::normal(K), "아", ""),
+            (Key::normal(S), "안", ""),
+            (Key::normal(S), "안ㄴ", ""),
+            (Key::normal(U), "안녀", ""),
+            (Key::normal(D), "안녕", ""),
+            (Key::normal(Backspace), "안", ""),
+            (Key::normal(Backspace), "", ""),
+            (Key::normal(Backspace), "", "PASS"),
+        ],
+    );
+}
+
+#[test]
+fn backspace_word() {
+    test_input_impl(
+        &backspace_config("dubeolsik", BackspaceMode::Word),
+        true,
+        &[
+            (Key::normal(D), "ㅇ", ""),
+            (Key::normal(K), "아", ""),
+            (Key::normal(S), "안", ""),
+            (Key::normal(S), "안ㄴ", ""),
+            (Key::normal(U), "안녀", ""),
+            (Key::normal(D), "안녕", ""),
+            (Key::normal(Backspace), "", ""),
+            (Key::normal(Backspace), "", "PASS"),
+        ],
+    );
+}
+
+#[test]
+fn backspace_word_keep_after_cursor() {
+    test_input_impl(
+        &backspace_config("dubeolsik", BackspaceMode::Word),
+        true,
+        &[
+            (Key::normal(G), "ㅎ", ""),
+            (Key::normal(K), "하", ""),
+            (Key::normal(S), "한", ""),
+            (Key::normal(R), "한ㄱ", ""),
+            (Key::normal(N), "한구", ""),
+            (Key::normal(R), "한국", ""),
+            (Key::normal(Left), "한국", ""),
+            (Key::normal(Backspace), "국", ""),
+            (Key::normal(Backspace), "국", ""),
+            (Key::normal(D), "ㅇ국", ""),
+        ],
+    );
+}
+
+#[test]
+fn backspace_at_word_start() {
+    for backspace_mode in [
+        BackspaceMode::Jamo,
+        BackspaceMode::Syllable,
+        BackspaceMode::Word,
+    ]
+    .iter()
+    .copied()
+    {
+        test_input_impl(
+            &backspace_config("dubeolsik", backspace_mode),
+            true,
+            &[
+                (Key::normal(R), "ㄱ", ""),
+                (Key::normal(K), "가", ""),
+                (Key::normal(S), "간", ""),
+                (Key::normal(K), "가나", ""),
+                (Key::normal(Home), "가나", ""),
+                (Key::normal(Backspace), "가나", ""),
+                (Key::normal(R), "ㄱ가나", ""),
+                (Key::normal(Esc), "", "ㄱ가나PASS"),
+            ],
+        );
+    }
+}
+
+#[test]
+fn backspace_syllable_hotkey() {
+    test_input_with_hotkey(
+        &[
+            (Key::normal(D), "ㅇ", ""),
+            (Key::normal(K), "아", ""),
+            (Key::normal(S), "안", ""),
+            (Key::normal(Backspace), "아", ""),
+            (Key::shift(Backspace), "", ""),
+            (Key::shift(Backspace), "", "PASS"),
+        ],
+        std::iter::once((
+            Key::shift(Backspace),
+            Hotkey::new(
+                HotkeyBehavior::BackspaceSyllable,
+                HotkeyResult::ConsumeIfProcessed,
+            )
+            .into(),
+        ))
+        .collect(),
+    );
+}
+
+#[test]
+fn word_boundary_space_bypass() {
+    test_word_input(&[
+        (Key::normal(D), "ㅇ", ""),
+        (Key::normal(K), "아", ""),
+        (Key::normal(S), "안", ""),
+        (Key::normal(Space), "", "안PASS"),
+    ]);
+}
+
+#[test]
+fn word_boundary_space_commit() {
+    test_input_impl(
+        &word_boundary_config(
+            "dubeolsik",
+            WordBoundary {
+                space: SpaceAction::Commit,
+                ..Default::default()
+            },
+        ),
+        true,
+        &[
+            (Key::normal(D), "ㅇ", ""),
+            (Key::normal(K), "아", ""),
+            (Key::normal(S), "안", ""),
+            (Key::normal(Space), "", "안 "),
+            (Key::normal(Space), "", "PASS"),
+        ],
+    );
+}
+
+#[test]
+fn word_boundary_digit() {
+    test_input_impl(
+        &word_boundary_config(
+            "dubeolsik",
+            WordBoundary {
+                digit: false,
+                ..Default::default()
+            },
+        ),
+        true,
+        &[
+            (Key::normal(D), "ㅇ", ""),
+            (Key::normal(K), "아", ""),
+            (Key::normal(S), "안", ""),
+            (Key::normal(One), "안1", ""),
+            (Key::normal(D), "안1ㅇ", ""),
+            (Key::normal(Backspace), "안1", ""),
+            (Key::normal(Backspace), "안", ""),
+            (Key::normal(Two), "안2", ""),
+            (Key::shift(One), "", "안2!"),
+        ],
+    );
+}
+
+#[test]
+fn word_boundary_max_length() {
+    test_input_impl(
+        &word_boundary_config(
+            "dubeolsik",
+            WordBoundary {
+                max_length: 2,
+                ..Default::default()
+            },
+        ),
+        true,
+        &[
+            (Key::normal(G), "ㅎ", ""),
+            (Key::normal(K), "하", ""),
+            (Key::normal(S), "한", ""),
+            (Key::normal(R), "한ㄱ", ""),
+            (Key::normal(N), "한구", ""),
+            (Key::normal(R), "한국", ""),
+            (Key::normal(D), "ㅇ", "한국"),
+            (Key::normal(J), "어", ""),
+            (Key::normal(Space), "", "어PASS"),
+        ],
+    );
+}
+
+#[test]
+fn preedit_segments() {
+    let config = default_config();
+    let mut engine = InputEngine::new(true);
+    engine.set_hangul_enable(true);
+
+    for code in [D, K, S, S].iter().copied() {
+        engine.press_key(Key::normal(code), &config);
+    }
+    assert_eq!(engine.preedit_str(), "안ㄴ");
+    assert_eq!(
+        engine.preedit_segments(),
+        &[
+            PreeditSegment::new(0, 3, PreeditStyle::Word),
+            PreeditSegment::new(3, 6, PreeditStyle::Composing),
+        ]
+    );
+
+    engine.press_key(Key::normal(Left), &config);
+    assert_eq!(
+        engine.preedit_segments(),
+        &[PreeditSegment::new(0, 6, PreeditStyle::Word)]
+    );
+
+    let mut engine = InputEngine::new(false);
+    engine.set_hangul_enable(true);
+
+    for code in [D, K].iter().copied() {
+        engine.press_key(Key::normal(code), &config);
+    }
+    assert_eq!(
+        engine.preedit_segments(),
+        &[PreeditSegment::new(0, 3, PreeditStyle::Composing)]
+    );
+}
+
+#[test]
+fn composition() {
+    let config = default_config();
+    let mut engine = InputEngine::new(false);
+    engine.set_hangul_enable(true);
+
+    assert_eq!(engine.composition(), Default::default());
+    assert_eq!(engine.composition().next_slot(), JamoSlot::Choseong);
+
+    engine.press_key(Key::normal(D), &config);
+    assert_eq!(engine.composition().cho, Some('ㅇ'));
+    assert_eq!(engine.composition().next_slot(), JamoSlot::Jungseong);
+
+    engine.press_key(Key::normal(H), &config);
+    let composition = engine.composition();
+    assert_eq!(composition.jung, Some('ㅗ'));
+    assert!(composition.compose_jung);
+    assert_eq!(composition.next_slot(), JamoSlot::Jongseong);
+
+    engine.press_key(Key::normal(K), &config);
+    engine.press_key(Key::normal(S), &config);
+    let composition = engine.composition();
+    assert_eq!(composition.jung, Some('ㅘ'));
+    assert_eq!(composition.jong, Some('ㄴ'));
+    assert_eq!(composition.next_slot(), JamoSlot::Choseong);
+}
+
+#[test]
+fn snapshot_restore() {
+    let config = default_config();
+    let mut engine = InputEngine::new(true);
+    engine.set_hangul_enable(true);
+
+    for code in [D, K, S, S, U].iter().copied() {
+        engine.press_key(Key::normal(code), &config);
+    }
+    engine.press_key(Key::normal(Left), &config);
+    assert_eq!(engine.preedit_str(), "안녀");
+
+    let snapshot = EngineSnapshot::from_yaml(&engine.snapshot().to_yaml()).unwrap();
+
+    let mut other = InputEngine::new(true);
+    other.restore(&snapshot);
+    assert!(other.is_hangul_enabled());
+    assert_eq!(other.preedit_str(), "안녀");
+    assert_eq!(other.preedit_cursor(), "안".len());
+
+    other.press_key(Key::normal(End), &config);
+    other.press_key(Key::normal(D), &config);
+    assert_eq!(other.preedit_str(), "안녀ㅇ");
+
+    engine.reset();
+    engine.set_hangul_enable(false);
+    engine.restore(&snapshot);
+    assert!(engine.is_hangul_enabled());
+    assert_eq!(engine.preedit_str(), "안녀");
+}
+
+#[test]
+fn reconvert() {
+    let config = default_config();
+    let mut engine = InputEngine::new(false);
+    engine.set_hangul_enable(true);
+
+    assert_eq!(engine.reconvert("abc 안녕", &config), 1);
+    assert_eq!(engine.preedit_str(), "녕");
+    engine.press_key(Key::normal(Backspace), &config);
+    assert_eq!(engine.preedit_str(), "녀");
+    // Preedit is not empty
+    assert_eq!(engine.reconvert("안", &config), 0);
+
+    engine.reset();
+    assert_eq!(engine.reconvert("abc", &config), 0);
+    assert_eq!(engine.preedit_str(), "");
+
+    let mut engine = InputEngine::new(true);
+    engine.set_hangul_enable(true);
+
+    assert_eq!(engine.reconvert("abc 안녕", &config), 2);
+    assert_eq!(engine.preedit_str(), "안녕");
+    engine.press_key(Key::normal(Backspace), &config);
+    engine.press_key(Key::normal(Backspace), &config);
+    assert_eq!(engine.preedit_str(), "안ㄴ");
+    engine.press_key(Key::normal(Backspace), &config);
+    engine.press_key(Key::normal(Backspace), &config);
+    assert_eq!(engine.preedit_str(), "아");
+
+    engine.reset();
+    assert_eq!(engine.reconvert("ㄱ", &config), 1);
+    engine.press_key(Key::normal(K), &config);
+    assert_eq!(engine.preedit_str(), "가");
+}
+
+#[test]
+fn reconvert_backspace() {
+    let config = Config::from_raw_config(
+        RawConfig {
+            layout: "dubeolsik".into(),
+            reconvert_backspace: true,
+            ..Default::default()
+        },
+        None,
+    );
+    let mut engine = InputEngine::new(false);
+    engine.set_hangul_enable(true);
+
+    engine.set_surrounding_text("abc 안");
+    assert_eq!(
+        engine.press_key(Key::normal(Backspace), &config),
+        InputResult::CONSUMED | InputResult::HAS_PREEDIT | InputResult::DELETE_SURROUNDING
+    );
+    assert_eq!(engine.delete_surrounding(), 1);
+    assert_eq!(engine.preedit_str(), "아");
+
+    engine.press_key(Key::normal(Backspace), &config);
+    assert_eq!(engine.delete_surrounding(), 0);
+    assert_eq!(engine.preedit_str(), "ㅇ");
+    engine.press_key(Key::normal(Backspace), &config);
+    // Surrounding text is already used
+    assert_eq!(
+        engine.press_key(Key::normal(Backspace), &config),
+        InputResult::empty()
+    );
+
+    // Disabled by default
+    engine.set_surrounding_text("안");
+    assert_eq!(
+        engine.press_key(Key::normal(Backspace), &default_config()),
+        InputResult::empty()
+    );
+}
+
+#[test]
+fn purpose_mode() {
+    let config = default_config();
+    let mut engine = InputEngine::new(false);
+    engine.set_hangul_enable(true);
+
+    assert_eq!(
+        engine.set_purpose(InputPurpose::Password, &config),
+        InputResult::LANGUAGE_CHANGED
+    );
+    assert!(!engine.is_hangul_enabled());
+    assert_eq!(
+        engine.press_key(Key::normal(R), &config),
+        InputResult::NEED_RESET
+    );
+
+    assert_eq!(
+        engine.set_purpose(InputPurpose::Number, &config),
+        InputResult::empty()
+    );
+
+    // Restore mode before password field
+    assert_eq!(
+        engine.set_purpose(InputPurpose::Normal, &config),
+        InputResult::LANGUAGE_CHANGED
+    );
+    assert!(engine.is_hangul_enabled());
+    assert_eq!(
+        engine.set_purpose(InputPurpose::Terminal, &config),
+        InputResult::empty()
+    );
+    assert!(engine.is_hangul_enabled());
+}
+
+fn hanja_config() -> Config {
+    let mut dict = String::from("# comment\n한:韓:나라 이름\n한:漢:한나라 한\n");
+
+    for ch in "加可家價架歌街假暇佳嘉".chars() {
+        dict.push_str(&format!("가:{}:\n", ch));
+    }
+
+    default_config().with_hanja_dict(HanjaDict::load_from(&dict))
+}
+
+#[test]
+fn hanja_candidates() {
+    let config = hanja_config();
+    let mut engine = InputEngine::new(false);
+    engine.set_hangul_enable(true);
+    engine.set_candidate_support(true);
+
+    engine.press_key(Key::normal(R), &config);
+    engine.press_key(Key::normal(K), &config);
+    assert_eq!(
+        engine.press_key(Key::normal(HangulHanja), &config),
+        InputResult::CONSUMED | InputResult::HAS_CANDIDATE | InputResult::HAS_PREEDIT
+    );
+
+    let list = engine.candidates().unwrap();
+    assert_eq!(list.len(), 11);
+    assert_eq!(list.page_count(), 2);
+    assert_eq!(list.page_items().len(), 9);
+    assert_eq!(list.page_items()[0].value, "加");
+
+    engine.press_key(Key::normal(Down), &config);
+    assert_eq!(engine.candidates().unwrap().selected(), 1);
+    engine.press_key(Key::normal(PageDown), &config);
+    let list = engine.candidates().unwrap();
+    assert_eq!(list.selected(), 9);
+    assert_eq!(list.page(), 1);
+    assert_eq!(list.page_items().len(), 2);
+
+    // Number out of page is ignored
+    assert_eq!(
+        engine.press_key(Key::normal(Three), &config),
+        InputResult::CONSUMED | InputResult::HAS_CANDIDATE | InputResult::HAS_PREEDIT
+    );
+    assert_eq!(
+        engine.press_key(Key::normal(Two), &config),
+        InputResult::CONSUMED | InputResult::NEED_RESET
+    );
+    assert!(engine.candidates().is_none());
+    assert_eq!(engine.commit_str(), "嘉");
+}
+
+#[test]
+fn hanja_candidates_close() {
+    let config = hanja_config();
+    let mut engine = InputEngine::new(false);
+    engine.set_hangul_enable(true);
+    engine.set_candidate_support(true);
+
+    engine.press_key(Key::normal(G), &config);
+    engine.press_key(Key::normal(K), &config);
+    engine.press_key(Key::normal(S), &config);
+    engine.press_key(Key::normal(HangulHanja), &config);
+    assert_eq!(
+        engine.candidates().unwrap().page_items()[1].comment,
+        "한나라 한"
+    );
+    assert_eq!(
+        engine.preedit_segments(),
+        &[PreeditSegment::new(0, 3, PreeditStyle::SelectedCandidate)]
+    );
+
+    // Esc closes list and keeps preedit
+    assert_eq!(
+        engine.press_key(Key::normal(Esc), &config),
+        InputResult::CONSUMED | InputResult::HAS_PREEDIT
+    );
+    assert!(engine.candidates().is_none());
+    assert_eq!(engine.preedit_str(), "한");
+    assert_eq!(
+        engine.preedit_segments(),
+        &[PreeditSegment::new(0, 3, PreeditStyle::Composing)]
+    );
+
+    // Other keys close list and are processed as usual
+    engine.press_key(Key::normal(HangulHanja), &config);
+    engine.press_key(Key::normal(Backspace), &config);
+    assert!(engine.candidates().is_none());
+    assert_eq!(engine.preedit_str(), "하");
+}
+
+#[test]
+fn hanja_candidates_surrounding() {
+    let config = hanja_config();
+    let mut engine = InputEngine::new(false);
+    engine.set_hangul_enable(true);
+
+    // Candidates are opened only when frontend supports them
+    engine.set_surrounding_text("abc 한");
+    engine.press_key(Key::normal(HangulHanja), &config);
+    assert!(engine.candidates().is_none());
+
+    engine.set_candidate_support(true);
+    engine.set_surrounding_text("abc 한");
+    engine.press_key(Key::normal(HangulHanja), &config);
+    assert_eq!(
+        engine.press_key(Key::normal(Enter), &config),
+        InputResult::CONSUMED | InputResult::NEED_RESET | InputResult::DELETE_SURROUNDING
+    );
+    assert_eq!(engine.delete_surrounding(), 1);
+    assert_eq!(engine.commit_str(), "韓");
+
+    engine.reset();
+    engine.set_purpose(InputPurpose::Password, &config);
+    engine.set_surrounding_text("abc 한");
+    engine.press_key(Key::normal(HangulHanja), &config);
+    assert!(engine.candidates().is_none());
+}
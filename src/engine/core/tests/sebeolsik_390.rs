@@ -1,46 +1,13 @@
-use kime_engine_core::{Config, InputEngine, InputResult, Key, KeyCode::*, RawConfig};
+mod common;
 
-#[track_caller]
-fn test_input(keys: &[(Key, &str, &str)]) {
-    let config = Config::from_raw_config(
-        RawConfig {
-            layout: "sebeolsik-390".into(),
-            ..Default::default()
-        },
-        None,
-    );
-
-    let mut engine = InputEngine::new(false);
-
-    engine.set_hangul_enable(true);
-
-    for (key, preedit, commit) in keys.iter().copied() {
-        eprintln!("Key: {:?}", key);
+use common::{backspace_config, test_input_impl, word_boundary_config};
+use kime_engine_core::{BackspaceMode, Key, KeyCode::*, WordBoundary};
 
-        let ret = engine.press_key(key, &config);
+const LAYOUT: &str = "sebeolsik-390";
 
-        eprintln!("Ret: {:?}", ret);
-
-        if ret.contains(InputResult::HAS_PREEDIT) {
-            assert_eq!(preedit, engine.preedit_str());
-        } else {
-            assert!(preedit.is_empty());
-        }
-
-        if !ret.contains(InputResult::CONSUMED) {
-            assert_eq!(commit, format!("{}PASS", engine.commit_str()));
-        } else if ret.intersects(InputResult::NEED_RESET | InputResult::NEED_FLUSH) {
-            assert_eq!(commit, engine.commit_str());
-        } else {
-            assert!(commit.is_empty());
-        }
-
-        if ret.contains(InputResult::NEED_RESET) {
-            engine.reset();
-        } else if ret.contains(InputResult::NEED_FLUSH) {
-            engine.flush();
-        }
-    }
+#[track_caller]
+fn test_input(keys: &[(Key, &str, &str)]) {
+    test_input_impl(&backspace_config(LAYOUT, BackspaceMode::Jamo), false, keys);
 }
 
 #[test]
@@ -105,3 +72,129 @@ fn s_number() {
 fn colon() {
     test_input(&[(Key::shift(SemiColon), "", ":")]);
 }
+
+#[test]
+fn backspace_jamo() {
+    test_input(&[
+        (Key::normal(J), "ㅇ", ""),
+        (Key::normal(F), "아", ""),
+        (Key::normal(S), "안", ""),
+        (Key::normal(Backspace), "아", ""),
+        (Key::normal(Backspace), "ㅇ", ""),
+        (Key::normal(Backspace), "", ""),
+        (Key::normal(Backspace), "", "PASS"),
+    ]);
+}
+
+#[test]
+fn backspace_syllable() {
+    test_input_impl(
+        &backspace_config(LAYOUT, BackspaceMode::Syllable),
+        false,
+        &[
+            (Key::normal(J), "ㅇ", ""),
+            (Key::normal(F), "아", ""),
+            (Key::normal(S), "안", ""),
+            (Key::normal(Backspace), "", ""),
+            (Key::normal(Backspace), "", "PASS"),
+        ],
+    );
+}
+
+#[test]
+fn backspace_syllable_word_commit() {
+    test_input_impl(
+        &backspace_config(LAYOUT, BackspaceMode::Syllable),
+        true,
+        &[
+            (Key::normal(J), "ㅇ", ""),
+            (Key::normal(F), "아", ""),
+            (Key::normal(S), "안", ""),
+            (Key::normal(H), "안ㄴ", ""),
+            (Key::normal(E), "안녀", ""),
+            (Key::normal(A), "안녕", ""),
+            (Key::normal(Backspace), "안", ""),
+            (Key::normal(Backspace), "", ""),
+            (Key::normal(Backspace), "", "PASS"),
+        ],
+    );
+}
+
+#[test]
+fn backspace_word() {
+    test_input_impl(
+        &backspace_config(LAYOUT, BackspaceMode::Word),
+        true,
+        &[
+            (Key::normal(J), "ㅇ", ""),
+            (Key::normal(F), "아", ""),
+            (Key::normal(S), "안", ""),
+            (Key::normal(H), "안ㄴ", ""),
+            (Key::normal(E), "안녀", ""),
+            (Key::normal(A), "안녕", ""),
+            (Key::normal(Backspace), "", ""),
+            (Key::normal(Backspace), "", "PASS"),
+        ],
+    );
+}
+
+#[test]
+fn word_boundary_default() {
+    test_input_impl(
+        &word_boundary_config(LAYOUT, WordBoundary::default()),
+        true,
+        &[
+            (Key::normal(J), "ㅇ", ""),
+            (Key::normal(F), "아", ""),
+            (Key::normal(S), "안", ""),
+            (Key::normal(Minus), "", "안-"),
+            (Key::normal(J), "ㅇ", ""),
+            (Key::shift(U), "", "ㅇ7"),
+        ],
+    );
+}
+
+#[test]
+fn word_boundary_punctuation() {
+    test_input_impl(
+        &word_boundary_config(
+            LAYOUT,
+            WordBoundary {
+                punctuation: false,
+                ..Default::default()
+            },
+        ),
+        true,
+        &[
+            (Key::normal(J), "ㅇ", ""),
+            (Key::normal(F), "아", ""),
+            (Key::normal(S), "안", ""),
+            (Key::normal(Minus), "안-", ""),
+            (Key::normal(J), "안-ㅇ", ""),
+            (Key::normal(F), "안-아", ""),
+            (Key::shift(U), "", "안-아7"),
+        ],
+    );
+}
+
+#[test]
+fn word_boundary_digit() {
+    test_input_impl(
+        &word_boundary_config(
+            LAYOUT,
+            WordBoundary {
+                digit: false,
+                ..Default::default()
+            },
+        ),
+        true,
+        &[
+            (Key::normal(J), "ㅇ", ""),
+            (Key::normal(F), "아", ""),
+            (Key::shift(U), "아7", ""),
+            (Key::normal(Backspace), "아", ""),
+            (Key::shift(U), "아7", ""),
+            (Key::normal(Minus), "", "아7-"),
+        ],
+    );
+}
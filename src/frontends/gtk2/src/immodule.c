@@ -2,6 +2,7 @@
 #include "str_buf.h"
 
 #include <stdio.h>
+#include <string.h>
 
 static GType KIME_TYPE_IM_CONTEXT = 0;
 static const guint FORWARDED_MASK = 1 << 25;
@@ -40,6 +41,11 @@ typedef struct KimeImContext {
   gboolean focus;
   gboolean preedit_visible;
   KimeConfig *config;
+  // key events consumed by pending hotkey sequence
+  GPtrArray *sequence_events;
+#if !GTK_CHECK_VERSION(3, 98, 4)
+  GArray *sequence_xevents;
+#endif
 } KimeImContext;
 
 #define KIME_IM_CONTEXT(var)                                                   \
@@ -75,6 +81,18 @@ void focus_in(GtkIMContext *im) {
 
   ctx->focus = TRUE;
   kime_engine_update_hangul_state(ctx->engine);
+
+  // show preedit kept by focus out policy
+  if (kime_engine_preedit_str(ctx->engine).len) {
+    update_preedit(ctx, TRUE);
+  }
+}
+
+void clear_sequence(KimeImContext *ctx) {
+  g_ptr_array_set_size(ctx->sequence_events, 0);
+#if !GTK_CHECK_VERSION(3, 98, 4)
+  g_array_set_size(ctx->sequence_xevents, 0);
+#endif
 }
 
 void kime_reset(KimeImContext *ctx) {
@@ -83,6 +101,7 @@ void kime_reset(KimeImContext *ctx) {
   str_buf_set_str(&ctx->buf, kime_engine_commit_str(ctx->engine));
   commit(ctx);
   kime_engine_reset(ctx->engine);
+  clear_sequence(ctx);
 }
 
 void reset(GtkIMContext *im) {
@@ -99,7 +118,16 @@ void focus_out(GtkIMContext *im) {
   debug("focus_out");
   ctx->focus = FALSE;
 
-  kime_reset(ctx);
+  KimeInputResult ret = kime_engine_focus_out(ctx->engine, ctx->config);
+
+  update_preedit(ctx, FALSE);
+  clear_sequence(ctx);
+
+  if (ret & KimeInputResult_NEED_RESET) {
+    str_buf_set_str(&ctx->buf, kime_engine_commit_str(ctx->engine));
+    commit(ctx);
+    kime_engine_reset(ctx->engine);
+  }
 }
 
 void put_event(KimeImContext *ctx, EventType *key) {
@@ -115,6 +143,43 @@ void put_event(KimeImContext *ctx, EventType *key) {
 #endif
 }
 
+EventType *copy_event(EventType *key) {
+#if GTK_CHECK_VERSION(3, 98, 4)
+  return gdk_event_ref(key);
+#else
+  return (EventType *)gdk_event_copy((GdkEvent *)key);
+#endif
+}
+
+void free_event(gpointer key) {
+#if GTK_CHECK_VERSION(3, 98, 4)
+  gdk_event_unref(key);
+#else
+  gdk_event_free(key);
+#endif
+}
+
+// send key events of unmatched hotkey sequence to client
+void replay_sequence(KimeImContext *ctx) {
+  for (guint i = 0; i < ctx->sequence_events->len; i++) {
+    put_event(ctx, g_ptr_array_index(ctx->sequence_events, i));
+  }
+
+#if !GTK_CHECK_VERSION(3, 98, 4)
+  // XPutBackEvent push event to head of queue so put back in reverse order
+  for (guint i = ctx->sequence_xevents->len; i > 0; i--) {
+    XEvent ev = g_array_index(ctx->sequence_xevents, XEvent, i - 1);
+    ev.xkey.state |= FORWARDED_MASK;
+    ev.type = KeyRelease;
+    XPutBackEvent(ev.xkey.display, &ev);
+    ev.type = KeyPress;
+    XPutBackEvent(ev.xkey.display, &ev);
+  }
+#endif
+
+  clear_sequence(ctx);
+}
+
 gboolean commit_event(KimeImContext *ctx, GdkModifierType state, guint keyval) {
   if (!(state & NOT_ENGLISH_MASK)) {
     uint32_t c = gdk_keyval_to_unicode(keyval);
@@ -129,11 +194,7 @@ gboolean commit_event(KimeImContext *ctx, GdkModifierType state, guint keyval) {
   return FALSE;
 }
 
-gboolean on_key_input(KimeImContext *ctx, guint16 code,
-                      KimeModifierState state) {
-  KimeInputResult ret =
-      kime_engine_press_key(ctx->engine, ctx->config, code, state);
-
+void process_input_result(KimeImContext *ctx, KimeInputResult ret) {
   if (ret & KimeInputResult_LANGUAGE_CHANGED) {
     kime_engine_update_hangul_state(ctx->engine);
   }
@@ -157,27 +218,35 @@ gboolean on_key_input(KimeImContext *ctx, guint16 code,
     update_preedit(ctx, TRUE);
   }
 
-  return (ret & KimeInputResult_CONSUMED) != 0;
+  if (ret & KimeInputResult_REPLAY_SEQUENCE) {
+    replay_sequence(ctx);
+  } else if (!(ret & KimeInputResult_SEQUENCE_PENDING)) {
+    clear_sequence(ctx);
+  }
 }
 
-gboolean filter_keypress(GtkIMContext *im, EventType *key) {
-  KIME_IM_CONTEXT(im);
-#if GTK_CHECK_VERSION(3, 98, 4)
-  if (gdk_event_get_event_type(key) != GDK_KEY_PRESS) {
-    return FALSE;
-  }
-  guint16 code = gdk_key_event_get_keycode(key);
-  guint keyval = gdk_key_event_get_keyval(key);
-  GdkModifierType state = gdk_event_get_modifier_state(key);
-#else
-  if (key->type != GDK_KEY_PRESS) {
-    return FALSE;
+KimeInputResult on_key_input(KimeImContext *ctx, guint16 code,
+                             KimeModifierState state) {
+  KimeInputResult ret =
+      kime_engine_press_key(ctx->engine, ctx->config, code, state);
+
+  process_input_result(ctx, ret);
+
+  return ret;
+}
+
+void on_key_release(KimeImContext *ctx, guint16 code,
+                    KimeModifierState state) {
+  KimeInputResult ret =
+      kime_engine_release_key(ctx->engine, ctx->config, code, state);
+
+  // tap hotkey is processed
+  if (ret) {
+    process_input_result(ctx, ret);
   }
-  guint16 code = key->hardware_keycode;
-  guint keyval = key->keyval;
-  GdkModifierType state = key->state;
-#endif
+}
 
+KimeModifierState modifier_state(GdkModifierType state) {
   KimeModifierState kime_state = 0;
 
   if (state & GDK_SHIFT_MASK) {
@@ -196,8 +265,43 @@ gboolean filter_keypress(GtkIMContext *im, EventType *key) {
     kime_state |= KimeModifierState_SUPER;
   }
 
-  return on_key_input(ctx, code, kime_state) ||
-         commit_event(ctx, state, keyval);
+  return kime_state;
+}
+
+gboolean filter_keypress(GtkIMContext *im, EventType *key) {
+  KIME_IM_CONTEXT(im);
+#if GTK_CHECK_VERSION(3, 98, 4)
+  gboolean pressed = gdk_event_get_event_type(key) == GDK_KEY_PRESS;
+  guint16 code = gdk_key_event_get_keycode(key);
+  guint keyval = gdk_key_event_get_keyval(key);
+  GdkModifierType state = gdk_event_get_modifier_state(key);
+#else
+  gboolean pressed = key->type == GDK_KEY_PRESS;
+  guint16 code = key->hardware_keycode;
+  guint keyval = key->keyval;
+  GdkModifierType state = key->state;
+#endif
+
+  if (state & FORWARDED_MASK) {
+    // replayed event
+    return FALSE;
+  }
+
+  KimeModifierState kime_state = modifier_state(state);
+
+  if (!pressed) {
+    on_key_release(ctx, code, kime_state);
+    return FALSE;
+  }
+
+  KimeInputResult ret = on_key_input(ctx, code, kime_state);
+
+  if (ret & KimeInputResult_SEQUENCE_PENDING) {
+    // keep event to replay when sequence is not matched
+    g_ptr_array_add(ctx->sequence_events, copy_event(key));
+  }
+
+  return (ret & KimeInputResult_CONSUMED) || commit_event(ctx, state, keyval);
 }
 
 void set_client(GtkIMContext *im, ClientType *client) {
@@ -232,7 +336,8 @@ void get_preedit_string(GtkIMContext *im, gchar **out, PangoAttrList **attrs,
       memcpy(g_s, s.ptr, s.len);
 
       if (cursor_pos) {
-        *cursor_pos = g_utf8_strlen(g_s, -1);
+        *cursor_pos = g_utf8_pointer_to_offset(
+            g_s, g_s + kime_engine_preedit_cursor(ctx->engine));
       }
       *out = g_s;
     }
@@ -267,6 +372,11 @@ GdkFilterReturn global_filter_event(GdkXEvent *xevent, GdkEvent *event,
     XKeyPressedEvent *kev = (XKeyPressedEvent *)xevent;
     KimeModifierState state = 0;
 
+    if (kev->state & FORWARDED_MASK) {
+      // replayed event
+      return GDK_FILTER_CONTINUE;
+    }
+
     if (kev->state & 0x1) {
       state |= KimeModifierState_SHIFT;
     }
@@ -283,7 +393,14 @@ GdkFilterReturn global_filter_event(GdkXEvent *xevent, GdkEvent *event,
       state |= KimeModifierState_SUPER;
     }
 
-    if (on_key_input(ctx, (guint16)kev->keycode, state)) {
+    KimeInputResult ret = on_key_input(ctx, (guint16)kev->keycode, state);
+
+    if (ret & KimeInputResult_SEQUENCE_PENDING) {
+      // keep event to replay when sequence is not matched
+      g_array_append_val(ctx->sequence_xevents, *native_event);
+    }
+
+    if (ret & KimeInputResult_CONSUMED) {
       // handled
       return GDK_FILTER_REMOVE;
     }
@@ -303,8 +420,15 @@ void im_context_init(KimeImContext *ctx, KimeImContextClass *klass) {
   ctx->signals = klass->signals;
   ctx->engine = kime_engine_new(klass->config);
   ctx->config = klass->config;
+  ctx->sequence_events = g_ptr_array_new_with_free_func(free_event);
+
+  const gchar *app = g_get_prgname();
+  if (app) {
+    kime_engine_set_app(ctx->engine, (const uint8_t *)app, strlen(app));
+  }
 
 #if !GTK_CHECK_VERSION(3, 98, 4)
+  ctx->sequence_xevents = g_array_new(FALSE, FALSE, sizeof(XEvent));
   gdk_window_add_filter(NULL, global_filter_event, ctx);
 #endif
 }
@@ -317,8 +441,10 @@ void im_context_finalize(GObject *obj) {
     ctx->client = NULL;
   }
   kime_engine_delete(ctx->engine);
+  g_ptr_array_unref(ctx->sequence_events);
 
 #if !GTK_CHECK_VERSION(3, 98, 4)
+  g_array_unref(ctx->sequence_xevents);
   gdk_window_remove_filter(NULL, global_filter_event, ctx);
 #endif
 }
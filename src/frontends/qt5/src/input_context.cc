@@ -1,18 +1,36 @@
 #include "input_context.hpp"
 
 #include <QtCore/QCoreApplication>
-#include <QtGui/QKeyEvent>
 #include <QtGui/QTextCharFormat>
 
+static std::unique_ptr<QKeyEvent> copy_key_event(QEvent::Type type,
+                                                 const QKeyEvent *e) {
+  return std::make_unique<QKeyEvent>(
+      type, e->key(), e->modifiers(), e->nativeScanCode(),
+      e->nativeVirtualKey(), e->nativeModifiers(), e->text(),
+      e->isAutoRepeat(), e->count());
+}
+
 KimeInputContext::KimeInputContext(kime::InputEngine *engine,
                                    const kime::Config *config)
-    : engine(engine), config(config) {}
+    : engine(engine), config(config) {
+  QByteArray app = QCoreApplication::applicationName().toUtf8();
+  kime::kime_engine_set_app(this->engine, (const uint8_t *)app.constData(),
+                            app.size());
+}
 
 KimeInputContext::~KimeInputContext() {}
 
 void KimeInputContext::update(Qt::InputMethodQueries queries) {}
 
-void KimeInputContext::commit() {}
+void KimeInputContext::commit() {
+#ifdef DEBUG
+  KIME_DEBUG << "commit"
+             << "\n";
+#endif
+  // app asks to commit preedit now, this is not focus out
+  this->reset();
+}
 
 void KimeInputContext::reset() {
 #ifdef DEBUG
@@ -22,13 +40,45 @@ void KimeInputContext::reset() {
   kime::kime_engine_clear_preedit(this->engine);
   commit_str(kime::kime_engine_commit_str(this->engine));
   kime::kime_engine_reset(this->engine);
+  this->sequence_events.clear();
 }
 
 void KimeInputContext::setFocusObject(QObject *object) {
+  if (this->focus_object && this->focus_object != object) {
+    this->focus_out();
+  }
+
   if (object) {
     kime::kime_engine_update_hangul_state(this->engine);
   }
   this->focus_object = object;
+
+  // show preedit kept by focus out policy
+  kime::RustStr s = kime::kime_engine_preedit_str(this->engine);
+  if (object && s.len) {
+    preedit_str(s);
+  }
+}
+
+void KimeInputContext::focus_out() {
+#ifdef DEBUG
+  KIME_DEBUG << "focus_out"
+             << "\n";
+#endif
+  kime::InputResult ret =
+      kime::kime_engine_focus_out(this->engine, this->config);
+  this->sequence_events.clear();
+
+  if (ret & kime::InputResult_NEED_RESET) {
+    commit_str(kime::kime_engine_commit_str(this->engine));
+    kime::kime_engine_reset(this->engine);
+  } else {
+    // hide preedit, it's shown again when focus in
+    kime::RustStr null_s;
+    null_s.ptr = nullptr;
+    null_s.len = 0;
+    commit_str(null_s);
+  }
 }
 
 bool KimeInputContext::isValid() const { return true; }
@@ -47,7 +97,8 @@ void KimeInputContext::invokeAction(QInputMethod::Action action,
 }
 
 bool KimeInputContext::filterEvent(const QEvent *event) {
-  if (event->type() != QEvent::KeyPress) {
+  if (event->type() != QEvent::KeyPress &&
+      event->type() != QEvent::KeyRelease) {
     return false;
   }
 
@@ -72,9 +123,34 @@ bool KimeInputContext::filterEvent(const QEvent *event) {
     state |= kime::ModifierState_SUPER;
   }
 
+  if (event->type() == QEvent::KeyRelease) {
+    kime::InputResult ret = kime_engine_release_key(
+        this->engine, this->config, (uint16_t)keyevent->nativeScanCode(),
+        state);
+
+    // tap hotkey is processed
+    if (ret) {
+      process_input_result(ret);
+    }
+
+    return false;
+  }
+
   kime::InputResult ret = kime_engine_press_key(
       this->engine, this->config, (uint16_t)keyevent->nativeScanCode(), state);
 
+  process_input_result(ret);
+
+  if (ret & kime::InputResult_SEQUENCE_PENDING) {
+    // keep event to replay when sequence is not matched
+    this->sequence_events.push_back(
+        copy_key_event(QEvent::KeyPress, keyevent));
+  }
+
+  return !!(ret & kime::InputResult_CONSUMED);
+}
+
+void KimeInputContext::process_input_result(kime::InputResult ret) {
   if (ret & kime::InputResult_LANGUAGE_CHANGED) {
     kime::kime_engine_update_hangul_state(this->engine);
   }
@@ -98,7 +174,22 @@ bool KimeInputContext::filterEvent(const QEvent *event) {
     commit_str(null_s);
   }
 
-  return !!(ret & kime::InputResult_CONSUMED);
+  if (ret & kime::InputResult_REPLAY_SEQUENCE) {
+    replay_sequence();
+  } else if (!(ret & kime::InputResult_SEQUENCE_PENDING)) {
+    this->sequence_events.clear();
+  }
+}
+
+void KimeInputContext::replay_sequence() {
+  // send key events of unmatched hotkey sequence to client
+  for (auto &e : this->sequence_events) {
+    QCoreApplication::sendEvent(this->focus_object, e.get());
+    auto release = copy_key_event(QEvent::KeyRelease, e.get());
+    QCoreApplication::sendEvent(this->focus_object, release.get());
+  }
+
+  this->sequence_events.clear();
 }
 
 void KimeInputContext::preedit_str(kime::RustStr s) {
@@ -106,6 +197,11 @@ void KimeInputContext::preedit_str(kime::RustStr s) {
   fmt.setFontUnderline(true);
   this->attributes.push_back(QInputMethodEvent::Attribute{
       QInputMethodEvent::AttributeType::TextFormat, 0, (int)s.len, fmt});
+  int cursor = QString::fromUtf8((const char *)(s.ptr),
+                                 kime::kime_engine_preedit_cursor(this->engine))
+                   .length();
+  this->attributes.push_back(QInputMethodEvent::Attribute{
+      QInputMethodEvent::AttributeType::Cursor, cursor, 1, QVariant()});
   QInputMethodEvent e(QString::fromUtf8((const char *)(s.ptr), s.len),
                       this->attributes);
   this->attributes.clear();
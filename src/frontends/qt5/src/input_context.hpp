@@ -1,8 +1,13 @@
 #include "kime-qt5.hpp"
 
+#include <QtCore/QPointer>
 #include <QtGui/QInputMethodEvent>
+#include <QtGui/QKeyEvent>
 #include <qpa/qplatforminputcontext.h>
 
+#include <memory>
+#include <vector>
+
 class KimeInputContext : public QPlatformInputContext {
   Q_OBJECT
 
@@ -21,11 +26,17 @@ public:
   void setFocusObject(QObject *object) override;
 
 private:
+  void focus_out();
+  void process_input_result(kime::InputResult ret);
+  void replay_sequence();
   void commit_str(kime::RustStr s);
   void preedit_str(kime::RustStr s);
 
   QList<QInputMethodEvent::Attribute> attributes;
   kime::InputEngine *engine = nullptr;
   const kime::Config *config = nullptr;
-  QObject *focus_object = nullptr;
+  // cleared by Qt when object is destroyed before focus out
+  QPointer<QObject> focus_object;
+  // key events consumed by pending hotkey sequence
+  std::vector<std::unique_ptr<QKeyEvent>> sequence_events;
 };
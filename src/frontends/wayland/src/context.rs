@@ -0,0 +1,553 @@
+use std::io;
+use std::os::unix::io::RawFd;
+use std::time::{Duration, Instant};
+
+use wayland_client::protocol::{wl_compositor::WlCompositor, wl_keyboard::KeyState, wl_shm::WlShm};
+
+use kime_engine_cffi::{
+    Config, InputEngine, InputPurpose, InputPurpose_Normal, InputPurpose_Password,
+    InputPurpose_Pin, InputPurpose_Terminal, InputResult, InputResult_CONSUMED,
+    InputResult_DELETE_SURROUNDING, InputResult_HAS_CANDIDATE, InputResult_HAS_PREEDIT,
+    InputResult_LANGUAGE_CHANGED, InputResult_NEED_FLUSH, InputResult_NEED_RESET,
+    InputResult_REPLAY_SEQUENCE, InputResult_SEQUENCE_PENDING, ModifierState,
+    PreeditStyle_Composing, PreeditStyle_SelectedCandidate,
+};
+
+use mio_timerfd::TimerFd;
+
+use crate::input_method::InputMethod;
+use crate::popup::{Popup, PopupState};
+use crate::xkb::Xkb;
+
+/// Double buffered state applied by `done` of input method v2
+pub struct InputMethodState {
+    pub activate: bool,
+    pub deactivate: bool,
+    /// Surrounding text and byte offset of cursor
+    pub surrounding_text: Option<(String, usize)>,
+    /// Text is changed by client not by input method
+    pub external_change: bool,
+    /// Content hint and purpose
+    pub content_type: Option<(u32, u32)>,
+}
+
+impl Default for InputMethodState {
+    fn default() -> Self {
+        Self {
+            activate: false,
+            deactivate: false,
+            surrounding_text: None,
+            external_change: false,
+            content_type: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct RepeatInfo {
+    /// The rate of repeating keys in characters per second
+    rate: i32,
+    /// Delay in milliseconds since key down until repeating starts
+    delay: i32,
+}
+
+#[derive(Clone, Copy)]
+enum PressState {
+    /// User is pressing no key, or user lifted last pressed key. But kime-wayland is ready for key
+    /// long-press.
+    NotPressing,
+    /// User is pressing a key.
+    Pressing {
+        /// User started pressing a key at this moment.
+        pressed_at: Instant,
+        /// `false` if user just started pressing a key. Soon, key repeating will be begin. `true`
+        /// if user have pressed a key for a long enough time, key repeating is happening right
+        /// now.
+        is_repeating: bool,
+
+        /// Key code used by wayland
+        key: u32,
+        /// Timestamp with millisecond granularity used by wayland. Their base is undefined, so
+        /// they can't be compared against system time (as obtained with clock_gettime or
+        /// gettimeofday). They can be compared with each other though, and for instance be used to
+        /// identify sequences of button presses as double or triple clicks.
+        ///
+        /// #### Reference
+        /// - https://wayland.freedesktop.org/docs/html/ch04.html#sect-Protocol-Input
+        wayland_time: u32,
+    },
+}
+
+impl PressState {
+    fn is_pressing(&self, query_key: u32) -> bool {
+        if let PressState::Pressing { key, .. } = self {
+            *key == query_key
+        } else {
+            false
+        }
+    }
+}
+
+pub struct KimeContext {
+    config: Config,
+    engine: InputEngine,
+    mod_state: ModifierState,
+    xkb: Xkb,
+    /// Raw modifiers received from compositor (depressed, latched, locked, group)
+    raw_mods: (u32, u32, u32, u32),
+    /// Raw modifiers sent to client (depressed, latched, locked, group)
+    vk_mods: (u32, u32, u32, u32),
+    /// Keys consumed by pending hotkey sequence (time, key, vk_mods)
+    sequence_keys: Vec<(u32, u32, (u32, u32, u32, u32))>,
+    /// Surrounding text before cursor
+    surrounding_text: String,
+    /// Focused field is secret, never log keys
+    sensitive: bool,
+    pub(crate) current_state: InputMethodState,
+    pub(crate) pending_state: InputMethodState,
+    /// `None` if compositor doesn't provide surfaces for popup
+    ///
+    /// Popup surface must be dropped before input method
+    popup: Option<Popup>,
+    pub(crate) im: InputMethod,
+    pub(crate) indicator_timer: TimerFd,
+    grab_activate: bool,
+
+    // Key repeat contexts
+    pub(crate) timer: TimerFd,
+    /// `None` if `KimeContext` have never received a `RepeatInfo` or repeat is disabled (i.e. rate
+    /// is zero). `Some(..)` if `RepeatInfo` is known and kime-wayland started tracking the press
+    /// state of keys.
+    repeat_state: Option<(RepeatInfo, PressState)>,
+}
+
+impl KimeContext {
+    pub fn new(im: InputMethod, timer: TimerFd, indicator_timer: TimerFd) -> Self {
+        let config = Config::load();
+        let engine = InputEngine::new(&config);
+
+        Self {
+            engine,
+            config,
+            mod_state: 0,
+            xkb: Xkb::new(),
+            raw_mods: (0, 0, 0, 0),
+            vk_mods: (0, 0, 0, 0),
+            sequence_keys: Vec::new(),
+            surrounding_text: String::new(),
+            sensitive: false,
+            current_state: InputMethodState::default(),
+            pending_state: InputMethodState::default(),
+            grab_activate: false,
+            popup: None,
+            im,
+            timer,
+            indicator_timer,
+            repeat_state: None,
+        }
+    }
+
+    /// Show candidates and indicator in popup surface, only supported by input method v2
+    pub fn init_popup(&mut self, compositor: &WlCompositor, shm: &WlShm) {
+        if let InputMethod::V2(v2) = &self.im {
+            match Popup::new(compositor, shm, &v2.im, self.config.xim_font()) {
+                Ok(popup) => self.popup = Some(popup),
+                Err(e) => log::warn!("Can't create popup: {}", e),
+            }
+        }
+
+        self.engine.set_candidate_support(self.popup.is_some());
+    }
+
+    fn commit(&mut self) {
+        self.im.commit();
+    }
+
+    fn commit_string(&mut self, s: String) {
+        self.im.commit_string(s);
+    }
+
+    fn clear_preedit(&mut self) {
+        self.im.set_preedit_string(String::new(), -1, -1);
+    }
+
+    fn preedit(&mut self) {
+        // Highlight composing character or text replaced by candidate, otherwise show cursor only
+        let (begin, end) = self
+            .engine
+            .preedit_segments()
+            .iter()
+            .find(|segment| {
+                segment.style == PreeditStyle_Composing
+                    || segment.style == PreeditStyle_SelectedCandidate
+            })
+            .map_or_else(
+                || {
+                    let cursor = self.engine.preedit_cursor();
+                    (cursor, cursor)
+                },
+                |segment| (segment.start, segment.end),
+            );
+        self.im
+            .set_preedit_string(self.engine.preedit_str().into(), begin as i32, end as i32);
+    }
+
+    pub(crate) fn set_surrounding_text(&mut self, text: &str, cursor: usize) {
+        self.surrounding_text.clear();
+        if let Some(before) = text.get(..cursor) {
+            self.surrounding_text.push_str(before);
+        }
+        self.engine.set_surrounding_text(&self.surrounding_text);
+    }
+
+    pub(crate) fn set_content_type(&mut self, hint: u32, purpose: u32) {
+        // hidden_text, sensitive_data
+        const SECRET_HINTS: u32 = 0x40 | 0x80;
+
+        let purpose: InputPurpose = if purpose > InputPurpose_Terminal {
+            InputPurpose_Normal
+        } else if purpose == InputPurpose_Normal && hint & SECRET_HINTS != 0 {
+            InputPurpose_Password
+        } else {
+            purpose
+        };
+
+        self.sensitive = purpose == InputPurpose_Password || purpose == InputPurpose_Pin;
+
+        if self.engine.set_purpose(&self.config, purpose) & InputResult_LANGUAGE_CHANGED != 0 {
+            self.engine.update_hangul_state();
+        }
+    }
+
+    fn show_indicator(&mut self) -> io::Result<()> {
+        let indicator = self.config.mode_indicator();
+
+        if !indicator.enable {
+            return Ok(());
+        }
+
+        if let Some(popup) = self.popup.as_mut() {
+            popup.show_indicator(self.engine.is_hangul_enabled());
+            self.indicator_timer
+                .set_timeout(&Duration::from_millis(indicator.duration))?;
+        }
+
+        Ok(())
+    }
+
+    fn hide_popup(&mut self) -> io::Result<()> {
+        if let Some(popup) = self.popup.as_mut() {
+            popup.hide();
+        }
+        self.indicator_timer.disarm()
+    }
+
+    fn update_popup(&mut self, ret: InputResult) -> io::Result<()> {
+        if ret & InputResult_HAS_CANDIDATE != 0 {
+            if let Some(popup) = self.popup.as_mut() {
+                popup.show_candidates(&self.engine);
+            }
+            self.indicator_timer.disarm()
+        } else if ret & InputResult_LANGUAGE_CHANGED != 0 {
+            self.show_indicator()
+        } else if self
+            .popup
+            .as_ref()
+            .map_or(false, |popup| popup.state() == PopupState::Candidates)
+        {
+            self.hide_popup()
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn handle_indicator_timer_ev(&mut self) -> io::Result<()> {
+        // Read timer, this MUST be called or timer will be broken
+        self.indicator_timer.read()?;
+
+        if let Some(popup) = self.popup.as_mut() {
+            if popup.state() == PopupState::Indicator {
+                popup.hide();
+            }
+        }
+
+        Ok(())
+    }
+
+    fn process_input_result(&mut self, ret: InputResult) -> io::Result<()> {
+        if ret & InputResult_LANGUAGE_CHANGED != 0 {
+            self.engine.update_hangul_state();
+        }
+
+        self.update_popup(ret)?;
+
+        if ret & InputResult_DELETE_SURROUNDING != 0 {
+            // Committed text is reloaded into preedit
+            let len: usize = self
+                .surrounding_text
+                .chars()
+                .rev()
+                .take(self.engine.delete_surrounding())
+                .map(char::len_utf8)
+                .sum();
+            self.im.delete_surrounding_text(len as u32);
+        }
+
+        if ret & InputResult_HAS_PREEDIT != 0 {
+            self.preedit();
+        } else {
+            self.clear_preedit();
+        }
+
+        if ret & (InputResult_NEED_RESET | InputResult_NEED_FLUSH) != 0 {
+            self.commit_string(self.engine.commit_str().into());
+
+            if ret & InputResult_NEED_RESET != 0 {
+                self.engine.reset();
+            } else {
+                self.engine.flush();
+            }
+        }
+
+        self.commit();
+
+        if ret & InputResult_REPLAY_SEQUENCE != 0 {
+            self.replay_sequence();
+        }
+
+        Ok(())
+    }
+
+    /// Send keys of unmatched hotkey sequence to client
+    fn replay_sequence(&mut self) {
+        for (time, key, (depressed, latched, locked, group)) in self.sequence_keys.drain(..) {
+            self.im.modifiers(depressed, latched, locked, group);
+            self.im.key(time, key, KeyState::Pressed);
+            self.im.key(time, key, KeyState::Released);
+        }
+
+        let (depressed, latched, locked, group) = self.vk_mods;
+        self.im.modifiers(depressed, latched, locked, group);
+    }
+
+    /// Text input is focused
+    pub fn activate(&mut self) {
+        self.engine.update_hangul_state();
+        self.grab_activate = true;
+    }
+
+    /// Focus lost, reset states
+    pub fn deactivate(&mut self) -> io::Result<()> {
+        // `focus_out` of commit policy is not applied, preedit is always discarded:
+        // client is already deactivated so preedit can't be committed,
+        // and engine is shared by every field of seat so preedit can't be kept for its field
+        self.engine.reset();
+        self.sequence_keys.clear();
+        self.set_surrounding_text("", 0);
+        self.set_content_type(0, InputPurpose_Normal);
+        self.grab_activate = false;
+
+        // Input deactivated, stop repeating
+        if let Some((_, ref mut press_state)) = self.repeat_state {
+            *press_state = PressState::NotPressing
+        }
+        self.timer.disarm()?;
+
+        self.hide_popup()
+    }
+
+    /// Client changed text by itself e.g. reset, drop preedit to avoid committing it twice
+    pub fn reset_by_client(&mut self) {
+        if self.grab_activate && !self.engine.preedit_str().is_empty() {
+            self.engine.reset();
+            self.clear_preedit();
+            self.commit();
+        }
+    }
+
+    /// Keymap of keyboard grab, fd is closed here
+    pub fn handle_keymap(&mut self, format: u32, fd: RawFd, size: u32) {
+        let changed = self.xkb.set_keymap(format, fd, size).unwrap_or_else(|e| {
+            log::warn!("Can't load keymap: {}", e);
+            true
+        });
+
+        // Keymap can be changed at runtime, clients must use same keymap
+        // Compositor may send grab keymap again when it's set to virtual keyboard, don't loop
+        if changed {
+            self.im.keymap(format, fd, size);
+        }
+        unsafe {
+            libc::close(fd);
+        }
+
+        // Modifier indices can be changed by new keymap
+        let (depressed, latched, locked, group) = self.raw_mods;
+        self.handle_modifiers(depressed, latched, locked, group);
+    }
+
+    pub fn handle_key(&mut self, time: u32, key: u32, state: KeyState) -> io::Result<()> {
+        if state == KeyState::Pressed {
+            if self.grab_activate {
+                let ret = self
+                    .engine
+                    .press_key(&self.config, (key + 8) as u16, self.mod_state);
+
+                self.process_input_result(ret)?;
+
+                if ret & InputResult_SEQUENCE_PENDING == 0 && !self.sequence_keys.is_empty() {
+                    // Sequence is matched or replayed, stop waiting its timeout
+                    self.sequence_keys.clear();
+                    self.timer.disarm()?;
+                }
+
+                if ret & InputResult_SEQUENCE_PENDING != 0 {
+                    // Keep key to replay when sequence is not matched
+                    self.sequence_keys.push((time, key, self.vk_mods));
+
+                    // Don't repeat sequence key
+                    if let Some((_, ref mut press_state)) = self.repeat_state {
+                        *press_state = PressState::NotPressing
+                    }
+                    self.wait_sequence_timeout()?;
+                } else if ret & InputResult_CONSUMED == 0 {
+                    // Bypassed key's repeat will be handled by the clients.
+                    //
+                    // Reference:
+                    //   https://github.com/swaywm/sway/pull/4932#issuecomment-774113129
+                    self.im.key(time, key, state);
+                } else {
+                    // If the key was not bypassed by IME, key repeat should be handled by the
+                    // IME. Start waiting for the key hold timer event.
+                    match self.repeat_state {
+                        Some((info, ref mut press_state)) if !press_state.is_pressing(key) => {
+                            let duration = Duration::from_millis(info.delay as u64);
+                            self.timer.set_timeout(&duration)?;
+                            *press_state = PressState::Pressing {
+                                pressed_at: Instant::now(),
+                                is_repeating: false,
+                                key,
+                                wayland_time: time,
+                            };
+                        }
+                        _ => {}
+                    }
+                }
+            } else {
+                // not activated so just skip
+                self.im.key(time, key, state);
+            }
+        } else {
+            // If user released the last pressed key, clear the timer and state
+            if let Some((.., ref mut press_state)) = self.repeat_state {
+                if press_state.is_pressing(key) {
+                    self.timer.disarm()?;
+                    *press_state = PressState::NotPressing;
+                }
+            }
+
+            if self.grab_activate {
+                let ret = self
+                    .engine
+                    .release_key(&self.config, (key + 8) as u16, self.mod_state);
+
+                // Tap hotkey is processed
+                if ret != 0 {
+                    self.process_input_result(ret)?;
+                }
+            }
+
+            self.im.key(time, key, state);
+        }
+
+        Ok(())
+    }
+
+    pub fn handle_repeat_info(&mut self, rate: i32, delay: i32) {
+        self.repeat_state = if rate == 0 {
+            // Zero rate means disabled repeat
+            //
+            // Reference:
+            //   https://github.com/swaywm/wlroots/blob/3d46d3f7/protocol/input-method-unstable-v2.xml#L444-L455
+            None
+        } else {
+            let info = RepeatInfo { rate, delay };
+            let press_state = self.repeat_state.map(|pair| pair.1);
+            Some((info, press_state.unwrap_or(PressState::NotPressing)))
+        }
+    }
+
+    pub fn handle_modifiers(&mut self, depressed: u32, latched: u32, locked: u32, group: u32) {
+        self.raw_mods = (depressed, latched, locked, group);
+        self.mod_state = self.xkb.update_mask(depressed, latched, locked, group);
+
+        // CapsLock is used as hotkey so don't let clients see it
+        let locked = if self.config.ignore_caps_lock() {
+            locked & !self.xkb.caps_lock_mask()
+        } else {
+            locked
+        };
+
+        self.vk_mods = (depressed, latched, locked, group);
+        self.im.modifiers(depressed, latched, locked, group);
+    }
+
+    /// Repeat timer is used for timeout of pending hotkey sequence since sequence keys are not
+    /// repeated, otherwise hint of sequence stays in preedit until next key
+    fn wait_sequence_timeout(&mut self) -> io::Result<()> {
+        match self.engine.sequence_timeout(&self.config) {
+            // Zero timeout disarms timer
+            Some(timeout) => self
+                .timer
+                .set_timeout(&timeout.max(Duration::from_millis(1))),
+            None => self.timer.disarm(),
+        }
+    }
+
+    pub fn handle_timer_ev(&mut self) -> io::Result<()> {
+        // Read timer, this MUST be called or timer will be broken
+        let overrun_count = self.timer.read()?;
+        if overrun_count != 1 {
+            log::warn!("Some timer events were not properly handled!");
+        }
+
+        if !self.sequence_keys.is_empty() {
+            let ret = self.engine.expire_sequence(&self.config);
+
+            return if ret == 0 {
+                self.wait_sequence_timeout()
+            } else {
+                self.process_input_result(ret)
+            };
+        }
+
+        if let Some((
+            info,
+            PressState::Pressing {
+                pressed_at,
+                ref mut is_repeating,
+                key,
+                wayland_time,
+            },
+        )) = self.repeat_state
+        {
+            if !*is_repeating {
+                // Start repeat
+                if !self.sensitive {
+                    log::trace!("Start repeating {}", key);
+                }
+                let interval = &Duration::from_secs_f64(1.0 / info.rate as f64);
+                self.timer.set_timeout_interval(interval)?;
+                *is_repeating = true;
+            }
+
+            // Emit key repeat event
+            let time = wayland_time + pressed_at.elapsed().as_millis() as u32;
+            self.handle_key(time, key, KeyState::Pressed)?;
+        } else {
+            log::warn!("Received timer event when it has never received RepeatInfo.");
+        }
+
+        Ok(())
+    }
+}
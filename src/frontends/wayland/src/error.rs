@@ -0,0 +1,47 @@
+use std::fmt;
+use std::io;
+
+use wayland_client::ConnectError;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Can't connect to compositor
+    Connect(ConnectError),
+    /// Connection is lost or epoll(), timer is broken
+    Io(io::Error),
+    /// Compositor doesn't provide any input method protocol
+    Unsupported,
+}
+
+impl Error {
+    /// Compositor can be restarted so retry unless it can't be used at all
+    pub fn is_retryable(&self) -> bool {
+        !matches!(self, Error::Unsupported)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Connect(e) => write!(f, "Can't connect to wayland display: {}", e),
+            Error::Io(e) => write!(f, "IO Error: {}", e),
+            Error::Unsupported => f.write_str(
+                "Compositor doesn't support zwp_input_method_v2 nor zwp_input_method_v1",
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<ConnectError> for Error {
+    fn from(e: ConnectError) -> Self {
+        Error::Connect(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
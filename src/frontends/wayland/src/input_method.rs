@@ -0,0 +1,173 @@
+use std::os::unix::io::RawFd;
+
+use wayland_client::{
+    protocol::{wl_keyboard::KeyState, wl_keyboard::WlKeyboard},
+    Filter, Main,
+};
+use wayland_protocols::unstable::{
+    input_method::v1::client::zwp_input_method_context_v1::ZwpInputMethodContextV1,
+    text_input::v1::client::zwp_text_input_v1::PreeditStyle,
+};
+use zwp_input_method::input_method_unstable_v2::{
+    zwp_input_method_keyboard_grab_v2::ZwpInputMethodKeyboardGrabV2,
+    zwp_input_method_v2::ZwpInputMethodV2,
+};
+use zwp_virtual_keyboard::virtual_keyboard_unstable_v1::zwp_virtual_keyboard_v1::ZwpVirtualKeyboardV1;
+
+use crate::v2::Events;
+
+/// Objects of `zwp_input_method_v2`, used by wlroots based compositors
+pub struct InputMethodV2 {
+    pub im: Main<ZwpInputMethodV2>,
+    pub vk: Main<ZwpVirtualKeyboardV1>,
+    /// Keyboard is grabbed only while text input is activated
+    pub grab: Option<Main<ZwpInputMethodKeyboardGrabV2>>,
+    pub filter: Filter<Events>,
+    /// Count of received `done` events, `commit` must use it as serial
+    pub serial: u32,
+}
+
+impl InputMethodV2 {
+    /// Compositor sends keymap and repeat info again for new grab
+    pub fn grab_keyboard(&mut self) {
+        if self.grab.is_none() {
+            let grab = self.im.grab_keyboard();
+            grab.assign(self.filter.clone());
+            self.grab = Some(grab);
+        }
+    }
+
+    pub fn release_keyboard(&mut self) {
+        if let Some(grab) = self.grab.take() {
+            grab.release();
+        }
+    }
+}
+
+impl Drop for InputMethodV2 {
+    fn drop(&mut self) {
+        self.release_keyboard();
+        self.vk.destroy();
+        self.im.destroy();
+    }
+}
+
+/// Objects of `zwp_input_method_v1`, used by KWin and Weston
+///
+/// Context is created for each activation of text input
+#[derive(Default)]
+pub struct InputMethodV1 {
+    pub context: Option<Main<ZwpInputMethodContextV1>>,
+    pub keyboard: Option<Main<WlKeyboard>>,
+    /// Serial of last `commit_state`
+    pub serial: u32,
+}
+
+impl InputMethodV1 {
+    pub fn deactivate(&mut self) {
+        self.keyboard = None;
+        if let Some(context) = self.context.take() {
+            context.destroy();
+        }
+    }
+}
+
+impl Drop for InputMethodV1 {
+    fn drop(&mut self) {
+        self.deactivate();
+    }
+}
+
+/// Requests to compositor, engine handling is shared by each protocol version
+pub enum InputMethod {
+    V2(InputMethodV2),
+    V1(InputMethodV1),
+}
+
+impl InputMethod {
+    pub fn commit_string(&mut self, text: String) {
+        match self {
+            InputMethod::V2(v2) => v2.im.commit_string(text),
+            InputMethod::V1(v1) => {
+                if let Some(context) = v1.context.as_ref() {
+                    context.commit_string(v1.serial, text);
+                }
+            }
+        }
+    }
+
+    /// Set preedit with highlighted range, cursor is placed at end of range
+    pub fn set_preedit_string(&mut self, text: String, begin: i32, end: i32) {
+        match self {
+            InputMethod::V2(v2) => v2.im.set_preedit_string(text, begin, end),
+            InputMethod::V1(v1) => {
+                if let Some(context) = v1.context.as_ref() {
+                    if begin >= 0 && begin < end {
+                        context.preedit_styling(
+                            begin as u32,
+                            (end - begin) as u32,
+                            PreeditStyle::Highlight.to_raw(),
+                        );
+                    }
+                    if end >= 0 {
+                        context.preedit_cursor(end);
+                    }
+                    // Preedit is committed by engine when focus is lost
+                    context.preedit_string(v1.serial, text, String::new());
+                }
+            }
+        }
+    }
+
+    /// Delete bytes before cursor
+    pub fn delete_surrounding_text(&mut self, before_length: u32) {
+        match self {
+            InputMethod::V2(v2) => v2.im.delete_surrounding_text(before_length, 0),
+            InputMethod::V1(v1) => {
+                if let Some(context) = v1.context.as_ref() {
+                    context.delete_surrounding_text(-(before_length as i32), before_length);
+                    // Deletion is applied with next commit
+                    context.commit_string(v1.serial, String::new());
+                }
+            }
+        }
+    }
+
+    /// Apply pending requests, v1 applies each request immediately
+    pub fn commit(&mut self) {
+        if let InputMethod::V2(v2) = self {
+            v2.im.commit(v2.serial);
+        }
+    }
+
+    /// Keymap of forwarded keys, v1 forwards keys with keymap of compositor
+    pub fn keymap(&mut self, format: u32, fd: RawFd, size: u32) {
+        if let InputMethod::V2(v2) = self {
+            v2.vk.keymap(format, fd, size);
+        }
+    }
+
+    /// Forward key to client
+    pub fn key(&mut self, time: u32, key: u32, state: KeyState) {
+        match self {
+            InputMethod::V2(v2) => v2.vk.key(time, key, state.to_raw()),
+            InputMethod::V1(v1) => {
+                if let Some(context) = v1.context.as_ref() {
+                    context.key(v1.serial, time, key, state.to_raw());
+                }
+            }
+        }
+    }
+
+    /// Forward modifiers to client
+    pub fn modifiers(&mut self, depressed: u32, latched: u32, locked: u32, group: u32) {
+        match self {
+            InputMethod::V2(v2) => v2.vk.modifiers(depressed, latched, locked, group),
+            InputMethod::V1(v1) => {
+                if let Some(context) = v1.context.as_ref() {
+                    context.modifiers(v1.serial, depressed, latched, locked, group);
+                }
+            }
+        }
+    }
+}
@@ -1,441 +1,339 @@
-use std::time::{Duration, Instant};
+mod context;
+mod error;
+mod input_method;
+mod popup;
+#[cfg(test)]
+mod tests;
+mod v1;
+mod v2;
+mod xkb;
+
+use std::collections::HashMap;
+use std::io;
+use std::time::Duration;
 
 use wayland_client::{
-    event_enum,
-    protocol::{wl_keyboard::KeyState, wl_seat::WlSeat},
-    DispatchData, Display, Filter, GlobalManager, Main,
+    protocol::{
+        wl_compositor::WlCompositor, wl_registry::WlRegistry, wl_seat::WlSeat, wl_shm::WlShm,
+    },
+    Attached, DispatchData, Display, Filter, GlobalEvent, GlobalManager, Main,
 };
+use wayland_protocols::unstable::input_method::v1::client::zwp_input_method_v1::ZwpInputMethodV1;
 
 use zwp_input_method::input_method_unstable_v2::{
-    zwp_input_method_keyboard_grab_v2::{Event as KeyEvent, ZwpInputMethodKeyboardGrabV2},
-    zwp_input_method_manager_v2::ZwpInputMethodManagerV2,
-    zwp_input_method_v2::{Event as ImEvent, ZwpInputMethodV2},
-};
-use zwp_virtual_keyboard::virtual_keyboard_unstable_v1::{
-    zwp_virtual_keyboard_manager_v1::ZwpVirtualKeyboardManagerV1,
-    zwp_virtual_keyboard_v1::ZwpVirtualKeyboardV1,
-};
-
-use kime_engine_cffi::{
-    Config, InputEngine, InputResult_CONSUMED, InputResult_HAS_PREEDIT,
-    InputResult_LANGUAGE_CHANGED, InputResult_NEED_FLUSH, InputResult_NEED_RESET, ModifierState,
-    ModifierState_ALT, ModifierState_CONTROL, ModifierState_SHIFT, ModifierState_SUPER,
+    zwp_input_method_manager_v2::ZwpInputMethodManagerV2, zwp_input_method_v2::Event as ImEvent,
 };
+use zwp_virtual_keyboard::virtual_keyboard_unstable_v1::zwp_virtual_keyboard_manager_v1::ZwpVirtualKeyboardManagerV1;
 
-use mio::{unix::SourceFd, Events as MioEvents, Interest, Poll, Token};
+use mio::{unix::SourceFd, Events as MioEvents, Interest, Poll, Registry, Token};
 use mio_timerfd::{ClockId, TimerFd};
 
-event_enum! {
-    Events |
-    Key => ZwpInputMethodKeyboardGrabV2,
-    Im => ZwpInputMethodV2
+use crate::context::KimeContext;
+use crate::error::Error;
+use crate::input_method::{InputMethod, InputMethodV1, InputMethodV2};
+
+/// First delay to reconnect after compositor is lost, doubled on each failure
+const RECONNECT_DELAY_MIN: Duration = Duration::from_millis(500);
+const RECONNECT_DELAY_MAX: Duration = Duration::from_secs(30);
+
+/// Contexts of each seat, or one context of compositor for input method v1
+struct KimeServer {
+    im_manager: Option<Main<ZwpInputMethodManagerV2>>,
+    vk_manager: Option<Main<ZwpVirtualKeyboardManagerV1>>,
+    popup_globals: Option<(Main<WlCompositor>, Main<WlShm>)>,
+    /// Seats announced before managers are bound
+    new_seats: Vec<(u32, Main<WlSeat>)>,
+    /// Key is registry name of seat or `zwp_input_method_v1`
+    contexts: HashMap<u32, KimeContext>,
+    registry: Registry,
+    /// First error raised in event callbacks
+    error: Option<io::Error>,
+    /// Input method is unavailable on every seat
+    unavailable: bool,
 }
 
-struct InputMethodState {
-    activate: bool,
-    deactivate: bool,
-}
-
-impl Default for InputMethodState {
-    fn default() -> Self {
+impl KimeServer {
+    pub fn new(registry: Registry) -> Self {
         Self {
-            activate: false,
-            deactivate: false,
+            im_manager: None,
+            vk_manager: None,
+            popup_globals: None,
+            new_seats: Vec::new(),
+            contexts: HashMap::new(),
+            registry,
+            error: None,
+            unavailable: false,
         }
     }
-}
 
-#[derive(Clone, Copy)]
-struct RepeatInfo {
-    /// The rate of repeating keys in characters per second
-    rate: i32,
-    /// Delay in milliseconds since key down until repeating starts
-    delay: i32,
-}
+    pub fn new_data<'a>(data: &'a mut DispatchData) -> &'a mut Self {
+        data.get::<Self>().unwrap()
+    }
 
-#[derive(Clone, Copy)]
-enum PressState {
-    /// User is pressing no key, or user lifted last pressed key. But kime-wayland is ready for key
-    /// long-press.
-    NotPressing,
-    /// User is pressing a key.
-    Pressing {
-        /// User started pressing a key at this moment.
-        pressed_at: Instant,
-        /// `false` if user just started pressing a key. Soon, key repeating will be begin. `true`
-        /// if user have pressed a key for a long enough time, key repeating is happening right
-        /// now.
-        is_repeating: bool,
-
-        /// Key code used by wayland
-        key: u32,
-        /// Timestamp with millisecond granularity used by wayland. Their base is undefined, so
-        /// they can't be compared against system time (as obtained with clock_gettime or
-        /// gettimeofday). They can be compared with each other though, and for instance be used to
-        /// identify sequences of button presses as double or triple clicks.
-        ///
-        /// #### Reference
-        /// - https://wayland.freedesktop.org/docs/html/ch04.html#sect-Protocol-Input
-        wayland_time: u32,
-    },
-}
+    /// Tokens of repeat timer and indicator timer, `Token(0)` is used by wayland socket
+    fn timer_tokens(id: u32) -> (Token, Token) {
+        let id = id as usize;
+        (Token(id * 2), Token(id * 2 + 1))
+    }
 
-impl PressState {
-    fn is_pressing(&self, query_key: u32) -> bool {
-        if let PressState::Pressing { key, .. } = self {
-            *key == query_key
-        } else {
-            false
+    /// Keep first error to stop event loop
+    fn set_error(&mut self, ret: io::Result<()>) {
+        if let Err(e) = ret {
+            self.error.get_or_insert(e);
         }
     }
-}
 
-struct KimeContext {
-    config: Config,
-    engine: InputEngine,
-    mod_state: ModifierState,
-    current_state: InputMethodState,
-    pending_state: InputMethodState,
-    vk: Main<ZwpVirtualKeyboardV1>,
-    im: Main<ZwpInputMethodV2>,
-    grab: Main<ZwpInputMethodKeyboardGrabV2>,
-    keymap_init: bool,
-    grab_activate: bool,
-    serial: u32,
-    // Have to consern Multi seats?
-
-    // Key repeat contexts
-    timer: TimerFd,
-    /// `None` if `KimeContext` have never received a `RepeatInfo` or repeat is disabled (i.e. rate
-    /// is zero). `Some(..)` if `RepeatInfo` is known and kime-wayland started tracking the press
-    /// state of keys.
-    repeat_state: Option<(RepeatInfo, PressState)>,
-}
+    pub fn add_seat(&mut self, id: u32, seat: Main<WlSeat>) -> io::Result<()> {
+        let (im_manager, vk_manager) = match (self.im_manager.as_ref(), self.vk_manager.as_ref()) {
+            (Some(im_manager), Some(vk_manager)) => (im_manager, vk_manager),
+            _ => {
+                // Managers are not bound yet, seats are unused with input method v1
+                self.new_seats.push((id, seat));
+                return Ok(());
+            }
+        };
 
-impl Drop for KimeContext {
-    fn drop(&mut self) {
-        self.grab.release();
-        self.vk.destroy();
-        self.im.destroy();
-    }
-}
+        let filter = Filter::new(move |ev, _filter, mut data| {
+            let server = KimeServer::new_data(&mut data);
 
-impl KimeContext {
-    pub fn new(
-        vk: Main<ZwpVirtualKeyboardV1>,
-        im: Main<ZwpInputMethodV2>,
-        grab: Main<ZwpInputMethodKeyboardGrabV2>,
-        timer: TimerFd,
-    ) -> Self {
-        let config = Config::load();
-        Self {
-            engine: InputEngine::new(&config),
-            config,
-            mod_state: 0,
-            current_state: InputMethodState::default(),
-            pending_state: InputMethodState::default(),
-            serial: 0,
-            keymap_init: false,
-            grab_activate: false,
-            vk,
-            im,
-            grab,
+            if let v2::Events::Im {
+                event: ImEvent::Unavailable,
+                ..
+            } = ev
+            {
+                server.disable_seat(id);
+                return;
+            }
+
+            let ctx = match server.contexts.get_mut(&id) {
+                Some(ctx) => ctx,
+                None => return,
+            };
+
+            let ret = match ev {
+                v2::Events::Key { event, .. } => ctx.handle_key_ev(event),
+                v2::Events::Im { event, .. } => ctx.handle_im_ev(event),
+            };
+
+            server.set_error(ret);
+        });
+
+        let vk = vk_manager.create_virtual_keyboard(&seat);
+        let im = im_manager.get_input_method(&seat);
+        im.assign(filter.clone());
+
+        let (timer, indicator_timer) = self.new_timers(id)?;
+        let mut ctx = KimeContext::new(
+            InputMethod::V2(InputMethodV2 {
+                im,
+                vk,
+                grab: None,
+                filter,
+                serial: 0,
+            }),
             timer,
-            repeat_state: None,
+            indicator_timer,
+        );
+
+        if let Some((compositor, shm)) = self.popup_globals.as_ref() {
+            ctx.init_popup(compositor, shm);
         }
-    }
 
-    pub fn new_data<'a>(data: &'a mut DispatchData) -> &'a mut Self {
-        data.get::<Self>().unwrap()
-    }
+        log::info!("Seat {} is added", id);
+        self.contexts.insert(id, ctx);
 
-    fn commit(&mut self) {
-        self.im.commit(self.serial);
-        self.serial += 1;
+        Ok(())
     }
 
-    fn commit_string(&mut self, s: String) {
-        self.im.commit_string(s);
-    }
+    /// Input method v1 isn't bound to seat, compositor activates it for focused text input
+    pub fn add_v1(&mut self, id: u32, im: Main<ZwpInputMethodV1>) -> io::Result<()> {
+        let filter = Filter::new(move |ev, filter, mut data| {
+            let server = KimeServer::new_data(&mut data);
+            let ctx = match server.contexts.get_mut(&id) {
+                Some(ctx) => ctx,
+                None => return,
+            };
+
+            let ret = match ev {
+                v1::Events::Im { event, .. } => ctx.handle_v1_im_ev(event, filter),
+                v1::Events::Context { event, .. } => {
+                    ctx.handle_v1_context_ev(event);
+                    Ok(())
+                }
+                v1::Events::Keyboard { event, .. } => ctx.handle_v1_key_ev(event),
+            };
+
+            server.set_error(ret);
+        });
 
-    fn clear_preedit(&mut self) {
-        self.im.set_preedit_string(String::new(), -1, -1);
+        im.assign(filter);
+
+        let (timer, indicator_timer) = self.new_timers(id)?;
+        let ctx = KimeContext::new(
+            InputMethod::V1(InputMethodV1::default()),
+            timer,
+            indicator_timer,
+        );
+
+        log::info!("Input method v1 is added");
+        self.contexts.insert(id, ctx);
+
+        Ok(())
     }
 
-    fn preedit(&mut self, s: String) {
-        let len = s.len();
-        self.im.set_preedit_string(s, 0, len as _);
+    /// Create repeat timer and indicator timer registered to the epoll()
+    fn new_timers(&self, id: u32) -> io::Result<(TimerFd, TimerFd)> {
+        let mut timer = TimerFd::new(ClockId::Monotonic)?;
+        let mut indicator_timer = TimerFd::new(ClockId::Monotonic)?;
+        let (timer_token, indicator_token) = Self::timer_tokens(id);
+
+        self.registry
+            .register(&mut timer, timer_token, Interest::READABLE)?;
+        self.registry
+            .register(&mut indicator_timer, indicator_token, Interest::READABLE)?;
+
+        Ok((timer, indicator_timer))
     }
 
-    pub fn handle_im_ev(&mut self, ev: ImEvent) {
-        match ev {
-            ImEvent::Activate => {
-                self.pending_state.activate = true;
-            }
-            ImEvent::Deactivate => {
-                self.pending_state.deactivate = true;
-            }
-            ImEvent::Unavailable => {
-                self.vk.destroy();
-                self.im.destroy();
-            }
-            ImEvent::Done => {
-                if !self.current_state.activate && self.pending_state.activate {
-                    self.engine.update_hangul_state();
-                    self.grab_activate = true;
-                } else if !self.current_state.deactivate && self.pending_state.deactivate {
-                    // Focus lost, reset states
-                    self.engine.reset();
-                    self.grab_activate = false;
-
-                    // Input deactivated, stop repeating
-                    self.timer.disarm().unwrap();
-                    if let Some((_, ref mut press_state)) = self.repeat_state {
-                        *press_state = PressState::NotPressing
-                    }
-                }
-                self.current_state = std::mem::take(&mut self.pending_state);
-            }
-            _ => {}
+    /// Add seats announced before managers are bound
+    pub fn add_new_seats(&mut self) -> io::Result<()> {
+        for (id, seat) in std::mem::take(&mut self.new_seats) {
+            self.add_seat(id, seat)?;
         }
+
+        Ok(())
     }
 
-    pub fn handle_key_ev(&mut self, ev: KeyEvent) {
-        match ev {
-            KeyEvent::Keymap { fd, format, size } => {
-                if !self.keymap_init {
-                    self.vk.keymap(format as _, fd, size);
-                    self.keymap_init = true;
-                }
-                unsafe {
-                    libc::close(fd);
-                }
-            }
-            KeyEvent::Key {
-                state, key, time, ..
-            } => {
-                // NOTE: Never read `serial` of KeyEvent. You should rely on serial of KimeContext
-                if state == KeyState::Pressed {
-                    if self.grab_activate {
-                        let ret =
-                            self.engine
-                                .press_key(&self.config, (key + 8) as u16, self.mod_state);
-
-                        if ret & InputResult_LANGUAGE_CHANGED != 0 {
-                            self.engine.update_hangul_state();
-                        }
-
-                        if ret & InputResult_HAS_PREEDIT != 0 {
-                            self.preedit(self.engine.preedit_str().into());
-                        } else {
-                            self.clear_preedit();
-                        }
-
-                        if ret & InputResult_NEED_RESET | InputResult_NEED_FLUSH != 0 {
-                            self.commit_string(self.engine.commit_str().into());
-
-                            if ret & InputResult_NEED_RESET != 0 {
-                                self.engine.reset();
-                            } else {
-                                self.engine.flush();
-                            }
-                        }
-
-                        self.commit();
-
-                        if ret & InputResult_CONSUMED == 0 {
-                            // Bypassed key's repeat will be handled by the clients.
-                            //
-                            // Reference:
-                            //   https://github.com/swaywm/sway/pull/4932#issuecomment-774113129
-                            self.vk.key(time, key, state as _);
-                        } else {
-                            // If the key was not bypassed by IME, key repeat should be handled by the
-                            // IME. Start waiting for the key hold timer event.
-                            match self.repeat_state {
-                                Some((info, ref mut press_state))
-                                    if !press_state.is_pressing(key) =>
-                                {
-                                    let duration = Duration::from_millis(info.delay as u64);
-                                    self.timer.set_timeout(&duration).unwrap();
-                                    *press_state = PressState::Pressing {
-                                        pressed_at: Instant::now(),
-                                        is_repeating: false,
-                                        key,
-                                        wayland_time: time,
-                                    };
-                                }
-                                _ => {}
-                            }
-                        }
-                    } else {
-                        // not activated so just skip
-                        self.vk.key(time, key, state as _);
-                    }
-                } else {
-                    // If user released the last pressed key, clear the timer and state
-                    if let Some((.., ref mut press_state)) = self.repeat_state {
-                        if press_state.is_pressing(key) {
-                            self.timer.disarm().unwrap();
-                            *press_state = PressState::NotPressing;
-                        }
-                    }
+    pub fn remove_seat(&mut self, id: u32) {
+        self.new_seats.retain(|(seat_id, _)| *seat_id != id);
 
-                    self.vk.key(time, key, state as _);
-                }
-            }
-            KeyEvent::Modifiers {
-                mods_depressed,
-                mods_latched,
-                mods_locked,
-                group,
-                ..
-            } => {
-                self.mod_state = 0;
-                if mods_depressed & 0x1 != 0 {
-                    self.mod_state |= ModifierState_SHIFT;
-                }
-                if mods_depressed & 0x4 != 0 {
-                    self.mod_state |= ModifierState_CONTROL;
-                }
-                if mods_depressed & 0x8 != 0 {
-                    self.mod_state |= ModifierState_ALT;
-                }
-                if mods_depressed & 0x40 != 0 {
-                    self.mod_state |= ModifierState_SUPER;
-                }
-                self.vk
-                    .modifiers(mods_depressed, mods_latched, mods_locked, group);
-            }
-            KeyEvent::RepeatInfo { rate, delay } => {
-                self.repeat_state = if rate == 0 {
-                    // Zero rate means disabled repeat
-                    //
-                    // Reference:
-                    //   https://github.com/swaywm/wlroots/blob/3d46d3f7/protocol/input-method-unstable-v2.xml#L444-L455
-                    None
-                } else {
-                    let info = RepeatInfo { rate, delay };
-                    let press_state = self.repeat_state.map(|pair| pair.1);
-                    Some((info, press_state.unwrap_or(PressState::NotPressing)))
-                }
-            }
-            _ => {}
+        if let Some(mut ctx) = self.contexts.remove(&id) {
+            self.registry.deregister(&mut ctx.timer).ok();
+            self.registry.deregister(&mut ctx.indicator_timer).ok();
+            log::info!("Seat {} is removed", id);
         }
     }
 
-    pub fn handle_timer_ev(&mut self) -> std::io::Result<()> {
-        // Read timer, this MUST be called or timer will be broken
-        let overrun_count = self.timer.read()?;
-        if overrun_count != 1 {
-            log::warn!("Some timer events were not properly handled!");
+    /// Other input method took the seat, release every object of it
+    fn disable_seat(&mut self, id: u32) {
+        log::warn!(
+            "Input method of seat {} is unavailable, other input method may be running",
+            id
+        );
+        self.remove_seat(id);
+
+        if self.contexts.is_empty() {
+            self.unavailable = true;
         }
+    }
 
-        if let Some((
-            info,
-            PressState::Pressing {
-                pressed_at,
-                ref mut is_repeating,
-                key,
-                wayland_time,
-            },
-        )) = self.repeat_state
-        {
-            if !*is_repeating {
-                // Start repeat
-                log::trace!("Start repeating {}", key);
-                let interval = &Duration::from_secs_f64(1.0 / info.rate as f64);
-                self.timer.set_timeout_interval(interval)?;
-                *is_repeating = true;
+    /// Select input method protocol by globals of compositor and create contexts
+    pub fn bind_globals(&mut self, globals: &GlobalManager) -> Result<(), Error> {
+        match (
+            globals.instantiate_exact::<ZwpInputMethodManagerV2>(1),
+            globals.instantiate_exact::<ZwpVirtualKeyboardManagerV1>(1),
+        ) {
+            (Ok(im_manager), Ok(vk_manager)) => {
+                log::info!("Use input method v2");
+                self.im_manager = Some(im_manager);
+                self.vk_manager = Some(vk_manager);
+                self.popup_globals = match (
+                    globals.instantiate_exact::<WlCompositor>(1),
+                    globals.instantiate_exact::<WlShm>(1),
+                ) {
+                    (Ok(compositor), Ok(shm)) => Some((compositor, shm)),
+                    _ => {
+                        log::warn!("Compositor doesn't support shm surface, popup is disabled");
+                        None
+                    }
+                };
+            }
+            _ => {
+                // KWin and Weston only provide input method v1
+                let id = globals
+                    .list()
+                    .into_iter()
+                    .find(|(_, interface, _)| interface == "zwp_input_method_v1")
+                    .map(|(id, _, _)| id)
+                    .ok_or(Error::Unsupported)?;
+                let im = globals
+                    .instantiate_exact::<ZwpInputMethodV1>(1)
+                    .map_err(|_| Error::Unsupported)?;
+
+                log::info!("Use input method v1");
+                self.add_v1(id, im)?;
             }
-
-            // Emit key repeat event
-            let ev = KeyEvent::Key {
-                serial: self.serial,
-                time: wayland_time + pressed_at.elapsed().as_millis() as u32,
-                key,
-                state: KeyState::Pressed,
-            };
-            self.serial += 1;
-            self.handle_key_ev(ev);
-        } else {
-            log::warn!("Received timer event when it has never received RepeatInfo.");
         }
 
+        self.add_new_seats()?;
+
         Ok(())
     }
+
+    pub fn handle_timer_ev(&mut self, token: Token) -> io::Result<()> {
+        let id = (token.0 / 2) as u32;
+
+        match self.contexts.get_mut(&id) {
+            Some(ctx) if token.0 % 2 == 0 => ctx.handle_timer_ev(),
+            Some(ctx) => ctx.handle_indicator_timer_ev(),
+            // Seat is removed
+            None => Ok(()),
+        }
+    }
 }
 
-fn main() {
-    kime_version::cli_boilerplate!();
+/// Add or remove seats announced by registry
+fn handle_global(event: GlobalEvent, registry: Attached<WlRegistry>, mut data: DispatchData) {
+    let server = KimeServer::new_data(&mut data);
 
-    assert!(
-        kime_engine_cffi::check_api_version(),
-        "Engine version mismatched"
-    );
+    match event {
+        GlobalEvent::New { id, interface, .. } if interface == "wl_seat" => {
+            let ret = server.add_seat(id, registry.bind::<WlSeat>(1, id));
+            server.set_error(ret);
+        }
+        GlobalEvent::Removed { id, interface } if interface == "wl_seat" => {
+            server.remove_seat(id);
+        }
+        _ => {}
+    }
+}
 
-    let display = Display::connect_to_env().expect("Failed to connect wayland display");
+/// Connect to compositor and run event loop
+///
+/// `delay` is reset once kime-wayland is initialized
+fn run(delay: &mut Duration) -> Result<(), Error> {
+    let display = Display::connect_to_env()?;
     let mut event_queue = display.create_event_queue();
     let attached_display = display.attach(event_queue.token());
-    let globals = GlobalManager::new(&attached_display);
-
-    event_queue.sync_roundtrip(&mut (), |_, _, _| ()).unwrap();
 
-    let seat = globals.instantiate_exact::<WlSeat>(1).expect("Load Seat");
-    let im_manager = globals
-        .instantiate_exact::<ZwpInputMethodManagerV2>(1)
-        .expect("Load InputManager");
-    let vk_manager = globals
-        .instantiate_exact::<ZwpVirtualKeyboardManagerV1>(1)
-        .expect("Load VirtualKeyboardManager");
+    // Initialize epoll() object
+    let mut poll = Poll::new()?;
+    let registry = poll.registry();
 
-    let filter = Filter::new(|ev, _filter, mut data| {
-        let ctx = KimeContext::new_data(&mut data);
+    const POLL_WAYLAND: Token = Token(0);
+    registry.register(
+        &mut SourceFd(&display.get_connection_fd()),
+        POLL_WAYLAND,
+        Interest::READABLE | Interest::WRITABLE,
+    )?;
 
-        match ev {
-            Events::Key { event, .. } => {
-                ctx.handle_key_ev(event);
-            }
-            Events::Im { event, .. } => {
-                ctx.handle_im_ev(event);
-            }
-        }
-    });
+    let mut server = KimeServer::new(registry.try_clone()?);
 
-    let vk = vk_manager.create_virtual_keyboard(&seat);
-    let im = im_manager.get_input_method(&seat);
-    let grab = im.grab_keyboard();
-    grab.assign(filter.clone());
-    im.assign(filter);
+    // Seats can be added or removed at runtime
+    let globals = GlobalManager::new_with_cb(&attached_display, handle_global);
 
-    // Initialize timer
-    let mut timer = TimerFd::new(ClockId::Monotonic).expect("Initialize timer");
+    event_queue.sync_roundtrip(&mut server, |_, _, _| ())?;
 
-    // Initialize epoll() object
-    let mut poll = Poll::new().expect("Initialize epoll()");
-    let registry = poll.registry();
+    // Initialize kime contexts
+    server.bind_globals(&globals)?;
+    event_queue.sync_roundtrip(&mut server, |_, _, _| ())?;
 
-    const POLL_WAYLAND: Token = Token(0);
-    registry
-        .register(
-            &mut SourceFd(&display.get_connection_fd()),
-            POLL_WAYLAND,
-            Interest::READABLE | Interest::WRITABLE,
-        )
-        .expect("Register wayland socket to the epoll()");
-
-    const POLL_TIMER: Token = Token(1);
-    registry
-        .register(&mut timer, POLL_TIMER, Interest::READABLE)
-        .expect("Register timer to the epoll()");
-
-    // Initialize kime context
-    let mut kime_ctx = KimeContext::new(vk, im, grab, timer);
-    event_queue
-        .sync_roundtrip(&mut kime_ctx, |_, _, _| ())
-        .unwrap();
+    if let Some(e) = server.error.take() {
+        return Err(e.into());
+    }
 
     log::info!("Server init success!");
+    *delay = RECONNECT_DELAY_MIN;
 
     // Non-blocking event loop
     //
@@ -445,6 +343,10 @@ fn main() {
     let stop_reason = 'main: loop {
         use std::io::ErrorKind;
 
+        if server.unavailable {
+            break Ok(());
+        }
+
         // Sleep until next event
         if let Err(e) = poll.poll(&mut events, None) {
             // Should retry on EINTR
@@ -460,12 +362,11 @@ fn main() {
         for event in &events {
             match event.token() {
                 POLL_WAYLAND => {}
-                POLL_TIMER => {
-                    if let Err(e) = kime_ctx.handle_timer_ev() {
+                token => {
+                    if let Err(e) = server.handle_timer_ev(token) {
                         break 'main Err(e);
                     }
                 }
-                _ => unreachable!(),
             }
         }
 
@@ -479,7 +380,11 @@ fn main() {
             }
         }
 
-        if let Err(e) = event_queue.dispatch_pending(&mut kime_ctx, |_, _, _| {}) {
+        if let Err(e) = event_queue.dispatch_pending(&mut server, |_, _, _| {}) {
+            break Err(e);
+        }
+
+        if let Some(e) = server.error.take() {
             break Err(e);
         }
 
@@ -492,8 +397,39 @@ fn main() {
         }
     };
 
-    match stop_reason {
-        Ok(()) => log::info!("Server finished gracefully"),
-        Err(e) => log::error!("Server aborted due to IO Error: {}", e),
+    // Release objects of compositor before disconnecting
+    drop(server);
+    display.flush().ok();
+
+    stop_reason.map_err(Error::Io)
+}
+
+fn main() {
+    kime_version::cli_boilerplate!();
+
+    assert!(
+        kime_engine_cffi::check_api_version(),
+        "Engine version mismatched"
+    );
+
+    let mut delay = RECONNECT_DELAY_MIN;
+
+    loop {
+        match run(&mut delay) {
+            Ok(()) => {
+                log::info!("Server finished gracefully");
+                break;
+            }
+            Err(e) if e.is_retryable() => {
+                log::error!("Server aborted: {}", e);
+                log::info!("Reconnect after {}ms", delay.as_millis());
+                std::thread::sleep(delay);
+                delay = (delay * 2).min(RECONNECT_DELAY_MAX);
+            }
+            Err(e) => {
+                log::error!("Server aborted: {}", e);
+                std::process::exit(1);
+            }
+        }
     }
 }
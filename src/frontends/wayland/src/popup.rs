@@ -0,0 +1,292 @@
+use std::io;
+use std::os::unix::io::RawFd;
+
+use wayland_client::{
+    protocol::{
+        wl_buffer::{Event as BufferEvent, WlBuffer},
+        wl_compositor::WlCompositor,
+        wl_shm::{Format, WlShm},
+        wl_shm_pool::WlShmPool,
+        wl_surface::WlSurface,
+    },
+    Main,
+};
+use zwp_input_method::input_method_unstable_v2::{
+    zwp_input_method_v2::ZwpInputMethodV2, zwp_input_popup_surface_v2::ZwpInputPopupSurfaceV2,
+};
+
+use kime_engine_cffi::InputEngine;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum PopupState {
+    Hidden,
+    Candidates,
+    Indicator,
+}
+
+/// Popup surface placed near text input by compositor
+///
+/// Contents are drawn by cairo in memory and copied into shm buffer, so GPU is not needed
+pub struct Popup {
+    surface: Main<WlSurface>,
+    popup_surface: Main<ZwpInputPopupSurfaceV2>,
+    shm: ShmPool,
+    /// Context used to measure text
+    measure: cairo::Context,
+    font: (String, f64),
+    lines: Vec<String>,
+    state: PopupState,
+}
+
+impl Popup {
+    pub fn new(
+        compositor: &WlCompositor,
+        shm: &WlShm,
+        im: &ZwpInputMethodV2,
+        (font, font_size): (&str, f64),
+    ) -> io::Result<Self> {
+        let surface = compositor.create_surface();
+        let popup_surface = im.get_input_popup_surface(&surface);
+        let shm = ShmPool::new(shm)?;
+
+        let measure = cairo::Context::new(
+            &cairo::ImageSurface::create(cairo::Format::ARgb32, 1, 1)
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("{:?}", e)))?,
+        );
+        measure.select_font_face(font, cairo::FontSlant::Normal, cairo::FontWeight::Normal);
+        measure.set_font_size(font_size);
+
+        Ok(Self {
+            surface,
+            popup_surface,
+            shm,
+            measure,
+            font: (font.into(), font_size),
+            lines: Vec::new(),
+            state: PopupState::Hidden,
+        })
+    }
+
+    pub fn state(&self) -> PopupState {
+        self.state
+    }
+
+    /// Show current page of candidate list with numbering
+    pub fn show_candidates(&mut self, engine: &InputEngine) {
+        let page = engine.candidate_page();
+
+        self.lines.clear();
+        for index in 0..page.len {
+            let (value, comment) = engine.candidate(index);
+            let line = if comment.is_empty() {
+                format!("{}. {}", index + 1, value)
+            } else {
+                format!("{}. {} {}", index + 1, value, comment)
+            };
+            self.lines.push(line);
+        }
+
+        if page.page_count > 1 {
+            self.lines
+                .push(format!("({}/{})", page.page + 1, page.page_count));
+        }
+
+        self.state = PopupState::Candidates;
+        self.draw(Some(page.selected));
+    }
+
+    /// Show current input mode
+    pub fn show_indicator(&mut self, hangul: bool) {
+        self.lines.clear();
+        self.lines.push(if hangul { "한" } else { "A" }.into());
+
+        self.state = PopupState::Indicator;
+        self.draw(None);
+    }
+
+    pub fn hide(&mut self) {
+        if self.state != PopupState::Hidden {
+            self.state = PopupState::Hidden;
+            self.surface.attach(None, 0, 0);
+            self.surface.commit();
+        }
+    }
+
+    fn draw(&mut self, selected: Option<usize>) {
+        let (font, font_size) = (&self.font.0, self.font.1);
+        let padding = (font_size * 0.4).ceil();
+        let extents = self.measure.font_extents();
+        let line_height = extents.height.ceil();
+
+        let text_width = self
+            .lines
+            .iter()
+            .map(|line| self.measure.text_extents(line).x_advance)
+            .fold(0.0, f64::max);
+
+        let width = (text_width + padding * 2.0).ceil() as i32;
+        let height = (line_height * self.lines.len() as f64 + padding * 2.0).ceil() as i32;
+
+        let mut image = match cairo::ImageSurface::create(cairo::Format::ARgb32, width, height) {
+            Ok(image) => image,
+            Err(e) => {
+                log::error!("Can't create popup image: {:?}", e);
+                return;
+            }
+        };
+
+        {
+            let cr = cairo::Context::new(&image);
+            cr.select_font_face(font, cairo::FontSlant::Normal, cairo::FontWeight::Normal);
+            cr.set_font_size(font_size);
+
+            cr.set_source_rgb(1.0, 1.0, 1.0);
+            cr.paint();
+
+            cr.set_source_rgb(0.5, 0.5, 0.5);
+            cr.set_line_width(1.0);
+            cr.rectangle(0.5, 0.5, width as f64 - 1.0, height as f64 - 1.0);
+            cr.stroke();
+
+            for (index, line) in self.lines.iter().enumerate() {
+                let top = padding + line_height * index as f64;
+
+                if selected == Some(index) {
+                    cr.set_source_rgb(0.8, 0.8, 0.8);
+                    cr.rectangle(1.0, top, width as f64 - 2.0, line_height);
+                    cr.fill();
+                }
+
+                cr.set_source_rgb(0.0, 0.0, 0.0);
+                cr.move_to(padding, top + extents.ascent);
+                cr.show_text(line);
+            }
+        }
+
+        image.flush();
+        let stride = image.get_stride();
+
+        let buffer = match image.get_data() {
+            Ok(data) => self.shm.write(&data, width, height, stride),
+            Err(e) => {
+                log::error!("Can't read popup image: {:?}", e);
+                return;
+            }
+        };
+
+        match buffer {
+            Ok(buffer) => {
+                self.surface.attach(Some(&buffer), 0, 0);
+                self.surface.damage(0, 0, width, height);
+                self.surface.commit();
+            }
+            Err(e) => log::error!("Can't write popup buffer: {}", e),
+        }
+    }
+}
+
+impl Drop for Popup {
+    fn drop(&mut self) {
+        self.popup_surface.destroy();
+        self.surface.destroy();
+    }
+}
+
+/// Shared memory split into two slots, next frame is written while compositor reads previous one
+struct ShmPool {
+    fd: RawFd,
+    pool: Main<WlShmPool>,
+    /// Size of one slot
+    slot_size: usize,
+    slot: usize,
+}
+
+impl ShmPool {
+    const INITIAL_SLOT_SIZE: usize = 64 * 1024;
+
+    fn new(shm: &WlShm) -> io::Result<Self> {
+        let fd = unsafe { libc::memfd_create(b"kime-popup\0".as_ptr() as _, libc::MFD_CLOEXEC) };
+
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let slot_size = Self::INITIAL_SLOT_SIZE;
+
+        if unsafe { libc::ftruncate(fd, (slot_size * 2) as _) } < 0 {
+            let err = io::Error::last_os_error();
+            unsafe {
+                libc::close(fd);
+            }
+            return Err(err);
+        }
+
+        Ok(Self {
+            fd,
+            pool: shm.create_pool(fd, (slot_size * 2) as i32),
+            slot_size,
+            slot: 0,
+        })
+    }
+
+    /// Copy ARGB32 image into next slot and create buffer of it
+    fn write(
+        &mut self,
+        data: &[u8],
+        width: i32,
+        height: i32,
+        stride: i32,
+    ) -> io::Result<Main<WlBuffer>> {
+        if data.len() > self.slot_size {
+            // Pool can only grow
+            self.slot_size = data.len();
+            if unsafe { libc::ftruncate(self.fd, (self.slot_size * 2) as _) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            self.pool.resize((self.slot_size * 2) as i32);
+        }
+
+        self.slot = (self.slot + 1) % 2;
+        let offset = self.slot * self.slot_size;
+
+        unsafe {
+            let ptr = libc::mmap(
+                std::ptr::null_mut(),
+                self.slot_size * 2,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                self.fd,
+                0,
+            );
+
+            if ptr == libc::MAP_FAILED {
+                return Err(io::Error::last_os_error());
+            }
+
+            std::ptr::copy_nonoverlapping(data.as_ptr(), (ptr as *mut u8).add(offset), data.len());
+            libc::munmap(ptr, self.slot_size * 2);
+        }
+
+        let buffer =
+            self.pool
+                .create_buffer(offset as i32, width, height, stride, Format::Argb8888);
+
+        // Buffer is created for each frame, destroy it when compositor is done with it
+        buffer.quick_assign(|buffer, event, _| {
+            if let BufferEvent::Release = event {
+                buffer.destroy();
+            }
+        });
+
+        Ok(buffer)
+    }
+}
+
+impl Drop for ShmPool {
+    fn drop(&mut self) {
+        self.pool.destroy();
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
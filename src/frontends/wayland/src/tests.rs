@@ -0,0 +1,508 @@
+//! Run `KimeServer` against in-process compositor which advertises input method v2 and virtual
+//! keyboard, and check requests sent by kime-wayland
+
+use std::cell::RefCell;
+use std::io::ErrorKind;
+use std::os::unix::{
+    io::{AsRawFd, IntoRawFd},
+    net::UnixStream,
+};
+use std::rc::Rc;
+use std::sync::Once;
+use std::time::Duration;
+
+use mio::{Poll, Token};
+use mio_timerfd::TimerFd;
+use wayland_client::{Display, EventQueue, GlobalManager};
+use wayland_protocols::misc::{
+    zwp_input_method_v2::server::{
+        zwp_input_method_keyboard_grab_v2::{Request as GrabRequest, ZwpInputMethodKeyboardGrabV2},
+        zwp_input_method_manager_v2::{
+            Request as ManagerRequest, ZwpInputMethodManagerV2 as ServerImManager,
+        },
+        zwp_input_method_v2::{Request as ImRequest, ZwpInputMethodV2 as ServerIm},
+    },
+    zwp_virtual_keyboard_v1::server::{
+        zwp_virtual_keyboard_manager_v1::{
+            Request as VkManagerRequest, ZwpVirtualKeyboardManagerV1 as ServerVkManager,
+        },
+        zwp_virtual_keyboard_v1::Request as VkRequest,
+    },
+};
+use wayland_server::{
+    protocol::{wl_keyboard::KeyState, wl_seat::WlSeat},
+    Display as ServerDisplay, Filter as ServerFilter, Main as ServerMain,
+};
+
+use crate::{handle_global, KimeServer};
+
+// evdev keycodes
+const KEY_R: u32 = 19;
+const KEY_K: u32 = 37;
+const KEY_SPACE: u32 = 57;
+const KEY_HANGEUL: u32 = 122;
+
+const PRESSED: u32 = 1;
+const RELEASED: u32 = 0;
+
+const REPEAT_DELAY: i32 = 10;
+
+/// Requests received by compositor
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Request {
+    Preedit(String, i32, i32),
+    CommitString(String),
+    DeleteSurrounding(u32, u32),
+    Commit(u32),
+    /// Forwarded by virtual keyboard (key, state)
+    Key(u32, u32),
+    Modifiers(u32, u32, u32, u32),
+}
+
+use self::Request::*;
+
+#[derive(Default)]
+struct CompositorState {
+    requests: Vec<Request>,
+    im: Option<ServerMain<ServerIm>>,
+    /// `None` if keyboard is not grabbed or grab is released
+    grab: Option<ServerMain<ZwpInputMethodKeyboardGrabV2>>,
+    im_destroyed: bool,
+}
+
+fn bind_im_manager(manager: ServerMain<ServerImManager>, state: Rc<RefCell<CompositorState>>) {
+    manager.quick_assign(move |_, request, _| {
+        if let ManagerRequest::GetInputMethod { input_method, .. } = request {
+            let im_state = state.clone();
+            input_method.quick_assign(move |_, request, _| {
+                let mut state = im_state.borrow_mut();
+
+                match request {
+                    ImRequest::SetPreeditString {
+                        text,
+                        cursor_begin,
+                        cursor_end,
+                    } => state.requests.push(Preedit(text, cursor_begin, cursor_end)),
+                    ImRequest::CommitString { text } => state.requests.push(CommitString(text)),
+                    ImRequest::DeleteSurroundingText {
+                        before_length,
+                        after_length,
+                    } => state
+                        .requests
+                        .push(DeleteSurrounding(before_length, after_length)),
+                    ImRequest::Commit { serial } => state.requests.push(Commit(serial)),
+                    ImRequest::GrabKeyboard { keyboard } => {
+                        let grab_state = im_state.clone();
+                        keyboard.quick_assign(move |_, request, _| {
+                            if let GrabRequest::Release = request {
+                                grab_state.borrow_mut().grab = None;
+                            }
+                        });
+                        state.grab = Some(keyboard);
+                    }
+                    ImRequest::Destroy => state.im_destroyed = true,
+                    ImRequest::GetInputPopupSurface { id, .. } => {
+                        id.quick_assign(|_, _, _| {});
+                    }
+                    _ => {}
+                }
+            });
+            state.borrow_mut().im = Some(input_method);
+        }
+    });
+}
+
+fn bind_vk_manager(manager: ServerMain<ServerVkManager>, state: Rc<RefCell<CompositorState>>) {
+    manager.quick_assign(move |_, request, _| {
+        if let VkManagerRequest::CreateVirtualKeyboard { id, .. } = request {
+            let state = state.clone();
+            id.quick_assign(move |_, request, _| {
+                let mut state = state.borrow_mut();
+
+                match request {
+                    VkRequest::Key {
+                        key,
+                        state: key_state,
+                        ..
+                    } => state.requests.push(Key(key, key_state)),
+                    VkRequest::Modifiers {
+                        mods_depressed,
+                        mods_latched,
+                        mods_locked,
+                        group,
+                    } => state.requests.push(Modifiers(
+                        mods_depressed,
+                        mods_latched,
+                        mods_locked,
+                        group,
+                    )),
+                    _ => {}
+                }
+            });
+        }
+    });
+}
+
+/// Use default config regardless of config of user
+fn isolate_config() {
+    static INIT: Once = Once::new();
+
+    INIT.call_once(|| {
+        std::env::set_var("XDG_CONFIG_HOME", "/nonexistent");
+        std::env::set_var("XDG_CONFIG_DIRS", "/nonexistent");
+    });
+}
+
+struct Harness {
+    compositor: ServerDisplay,
+    state: Rc<RefCell<CompositorState>>,
+    display: Display,
+    event_queue: EventQueue,
+    server: KimeServer,
+    globals: GlobalManager,
+    _poll: Poll,
+    time: u32,
+}
+
+impl Harness {
+    fn new() -> Self {
+        isolate_config();
+
+        let state = Rc::new(RefCell::new(CompositorState::default()));
+        let mut compositor = ServerDisplay::new();
+
+        compositor.create_global::<WlSeat, _>(
+            1,
+            ServerFilter::new(|(seat, _): (ServerMain<WlSeat>, u32), _, _| {
+                seat.quick_assign(|_, _, _| {});
+            }),
+        );
+        let im_state = state.clone();
+        compositor.create_global::<ServerImManager, _>(
+            1,
+            ServerFilter::new(
+                move |(manager, _): (ServerMain<ServerImManager>, u32), _, _| {
+                    bind_im_manager(manager, im_state.clone());
+                },
+            ),
+        );
+        let vk_state = state.clone();
+        compositor.create_global::<ServerVkManager, _>(
+            1,
+            ServerFilter::new(
+                move |(manager, _): (ServerMain<ServerVkManager>, u32), _, _| {
+                    bind_vk_manager(manager, vk_state.clone());
+                },
+            ),
+        );
+
+        let (server_socket, client_socket) = UnixStream::pair().unwrap();
+        client_socket.set_nonblocking(true).unwrap();
+        unsafe {
+            compositor.create_client(server_socket.into_raw_fd(), &mut ());
+        }
+
+        let display = unsafe { Display::from_fd(client_socket.into_raw_fd()) }.unwrap();
+        let event_queue = display.create_event_queue();
+        let attached_display = display.attach(event_queue.token());
+
+        let poll = Poll::new().unwrap();
+        let server = KimeServer::new(poll.registry().try_clone().unwrap());
+        let globals = GlobalManager::new_with_cb(&attached_display, handle_global);
+
+        let mut harness = Self {
+            compositor,
+            state,
+            display,
+            event_queue,
+            server,
+            globals,
+            _poll: poll,
+            time: 0,
+        };
+
+        harness.pump();
+        harness.server.bind_globals(&harness.globals).unwrap();
+        harness.pump();
+
+        assert!(harness.state.borrow().im.is_some());
+
+        harness
+    }
+
+    /// Exchange messages until both sides have nothing to do
+    fn pump(&mut self) {
+        for _ in 0..4 {
+            self.display.flush().unwrap();
+            self.compositor
+                .dispatch(Duration::from_millis(0), &mut ())
+                .unwrap();
+            self.compositor.flush_clients(&mut ());
+
+            if let Some(guard) = self.event_queue.prepare_read() {
+                if let Err(e) = guard.read_events() {
+                    assert_eq!(e.kind(), ErrorKind::WouldBlock);
+                }
+            }
+
+            self.event_queue
+                .dispatch_pending(&mut self.server, |_, _, _| {})
+                .unwrap();
+        }
+    }
+
+    fn with_im(&mut self, f: impl FnOnce(&ServerIm)) {
+        f(self.state.borrow().im.as_ref().unwrap());
+        self.pump();
+    }
+
+    fn activate(&mut self) {
+        self.with_im(|im| {
+            im.activate();
+            im.done();
+        });
+    }
+
+    fn deactivate(&mut self) {
+        self.with_im(|im| {
+            im.deactivate();
+            im.done();
+        });
+    }
+
+    fn done(&mut self) {
+        self.with_im(|im| im.done());
+    }
+
+    fn repeat_info(&mut self, rate: i32, delay: i32) {
+        self.state
+            .borrow()
+            .grab
+            .as_ref()
+            .unwrap()
+            .repeat_info(rate, delay);
+        self.pump();
+    }
+
+    fn key(&mut self, key: u32, state: KeyState) {
+        self.time += 1;
+        // kime-wayland must not depend on serial of key event
+        self.state
+            .borrow()
+            .grab
+            .as_ref()
+            .unwrap()
+            .key(0, self.time, key, state);
+        self.pump();
+    }
+
+    fn tap(&mut self, key: u32) {
+        self.key(key, KeyState::Pressed);
+        self.key(key, KeyState::Released);
+    }
+
+    /// Wait for repeat timer of seat and handle it as event loop does
+    ///
+    /// Disarmed timer is not waited, handling it returns error
+    fn fire_repeat_timer(&mut self) -> std::io::Result<()> {
+        let id = *self.server.contexts.keys().next().unwrap();
+        wait_timer(&self.server.contexts[&id].timer);
+
+        let ret = self.server.handle_timer_ev(Token(id as usize * 2));
+        self.pump();
+        ret
+    }
+
+    fn take_requests(&mut self) -> Vec<Request> {
+        std::mem::take(&mut self.state.borrow_mut().requests)
+    }
+
+    fn toggle_hangul(&mut self) {
+        self.tap(KEY_HANGEUL);
+        self.take_requests();
+    }
+}
+
+/// Block until timerfd expires, return immediately when it's disarmed or already expired
+fn wait_timer(timer: &TimerFd) {
+    let spec = timer.gettime().unwrap();
+
+    if spec.it_value.tv_sec == 0 && spec.it_value.tv_nsec == 0 {
+        return;
+    }
+
+    let mut pollfd = libc::pollfd {
+        fd: timer.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    // Generous bound so broken timer fails test instead of hanging
+    assert_eq!(unsafe { libc::poll(&mut pollfd, 1, 5000) }, 1);
+}
+
+#[test]
+fn grab_keyboard_while_activated() {
+    let mut harness = Harness::new();
+
+    // Keys go to client directly before activation
+    assert!(harness.state.borrow().grab.is_none());
+
+    harness.activate();
+    assert!(harness.state.borrow().grab.is_some());
+
+    harness.deactivate();
+    assert!(harness.state.borrow().grab.is_none());
+
+    // Grab is created again for next activation
+    harness.activate();
+    assert!(harness.state.borrow().grab.is_some());
+    assert!(harness.take_requests().is_empty());
+}
+
+#[test]
+fn unavailable_releases_seat() {
+    let mut harness = Harness::new();
+
+    harness.with_im(|im| im.unavailable());
+
+    assert!(harness.server.contexts.is_empty());
+    assert!(harness.server.unavailable);
+    assert!(harness.state.borrow().im_destroyed);
+}
+
+#[test]
+fn preedit_and_commit() {
+    let mut harness = Harness::new();
+
+    harness.activate();
+    assert!(harness.take_requests().is_empty());
+
+    harness.key(KEY_HANGEUL, KeyState::Pressed);
+    assert_eq!(
+        harness.take_requests(),
+        [Preedit("".into(), -1, -1), Commit(1)]
+    );
+
+    // Released keys are always forwarded
+    harness.key(KEY_HANGEUL, KeyState::Released);
+    assert_eq!(harness.take_requests(), [Key(KEY_HANGEUL, RELEASED)]);
+
+    harness.key(KEY_R, KeyState::Pressed);
+    assert_eq!(
+        harness.take_requests(),
+        [Preedit("ㄱ".into(), 0, 3), Commit(1)]
+    );
+
+    harness.key(KEY_K, KeyState::Pressed);
+    assert_eq!(
+        harness.take_requests(),
+        [Preedit("가".into(), 0, 3), Commit(1)]
+    );
+
+    harness.key(KEY_SPACE, KeyState::Pressed);
+    assert_eq!(
+        harness.take_requests(),
+        [
+            Preedit("".into(), -1, -1),
+            CommitString("가".into()),
+            Commit(1),
+            Key(KEY_SPACE, PRESSED),
+        ]
+    );
+}
+
+#[test]
+fn commit_serial_counts_done() {
+    let mut harness = Harness::new();
+
+    harness.activate();
+    harness.toggle_hangul();
+
+    harness.key(KEY_R, KeyState::Pressed);
+    assert_eq!(
+        harness.take_requests(),
+        [Preedit("ㄱ".into(), 0, 3), Commit(1)]
+    );
+
+    harness.done();
+    harness.done();
+    assert!(harness.take_requests().is_empty());
+
+    harness.key(KEY_K, KeyState::Pressed);
+    assert_eq!(
+        harness.take_requests(),
+        [Preedit("가".into(), 0, 3), Commit(3)]
+    );
+}
+
+#[test]
+fn deactivate_resets_preedit() {
+    let mut harness = Harness::new();
+
+    harness.activate();
+    harness.toggle_hangul();
+
+    harness.key(KEY_R, KeyState::Pressed);
+    assert_eq!(
+        harness.take_requests(),
+        [Preedit("ㄱ".into(), 0, 3), Commit(1)]
+    );
+
+    // Client is already unfocused, so nothing is sent
+    harness.deactivate();
+    assert!(harness.take_requests().is_empty());
+
+    harness.activate();
+    assert!(harness.take_requests().is_empty());
+
+    harness.key(KEY_R, KeyState::Pressed);
+    assert_eq!(
+        harness.take_requests(),
+        [Preedit("ㄱ".into(), 0, 3), Commit(3)]
+    );
+}
+
+#[test]
+fn repeat_consumed_key() {
+    let mut harness = Harness::new();
+
+    harness.activate();
+    harness.repeat_info(50, REPEAT_DELAY);
+    harness.toggle_hangul();
+
+    harness.key(KEY_R, KeyState::Pressed);
+    assert_eq!(
+        harness.take_requests(),
+        [Preedit("ㄱ".into(), 0, 3), Commit(1)]
+    );
+
+    harness.fire_repeat_timer().unwrap();
+    assert_eq!(
+        harness.take_requests(),
+        [Preedit("ㄲ".into(), 0, 3), Commit(1)]
+    );
+
+    harness.key(KEY_R, KeyState::Released);
+    assert_eq!(harness.take_requests(), [Key(KEY_R, RELEASED)]);
+
+    // Timer is disarmed by release
+    assert!(harness.fire_repeat_timer().is_err());
+    assert!(harness.take_requests().is_empty());
+}
+
+#[test]
+fn bypassed_key_is_repeated_by_client() {
+    let mut harness = Harness::new();
+
+    harness.activate();
+    harness.repeat_info(50, REPEAT_DELAY);
+
+    harness.key(KEY_R, KeyState::Pressed);
+    assert_eq!(
+        harness.take_requests(),
+        [Preedit("".into(), -1, -1), Commit(1), Key(KEY_R, PRESSED)]
+    );
+
+    assert!(harness.fire_repeat_timer().is_err());
+    assert!(harness.take_requests().is_empty());
+}
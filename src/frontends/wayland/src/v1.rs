@@ -0,0 +1,122 @@
+use std::io;
+
+use wayland_client::{
+    event_enum,
+    protocol::wl_keyboard::{Event as KeyEvent, WlKeyboard},
+    Filter,
+};
+use wayland_protocols::unstable::input_method::v1::client::{
+    zwp_input_method_context_v1::{Event as ContextEvent, ZwpInputMethodContextV1},
+    zwp_input_method_v1::{Event as ImEvent, ZwpInputMethodV1},
+};
+
+use crate::context::KimeContext;
+use crate::input_method::InputMethod;
+
+event_enum! {
+    Events |
+    Im => ZwpInputMethodV1,
+    Context => ZwpInputMethodContextV1,
+    Keyboard => WlKeyboard
+}
+
+/// Convert purpose of text-input-v1 which doesn't have `pin`
+fn purpose_from_v1(purpose: u32) -> u32 {
+    if purpose >= 9 {
+        purpose + 1
+    } else {
+        purpose
+    }
+}
+
+impl KimeContext {
+    pub fn handle_v1_im_ev(&mut self, ev: ImEvent, filter: &Filter<Events>) -> io::Result<()> {
+        match ev {
+            ImEvent::Activate { id } => {
+                if let InputMethod::V1(v1) = &mut self.im {
+                    // Previous context can be left when focus is moved
+                    v1.deactivate();
+
+                    let keyboard = id.grab_keyboard();
+                    keyboard.assign(filter.clone());
+                    id.assign(filter.clone());
+
+                    v1.context = Some(id);
+                    v1.keyboard = Some(keyboard);
+                    v1.serial = 0;
+                }
+
+                self.activate();
+            }
+            ImEvent::Deactivate { context } => {
+                let current = match &self.im {
+                    InputMethod::V1(v1) => v1
+                        .context
+                        .as_ref()
+                        .map_or(false, |current| current.as_ref().equals(context.as_ref())),
+                    InputMethod::V2(_) => false,
+                };
+
+                if current {
+                    self.deactivate()?;
+                    if let InputMethod::V1(v1) = &mut self.im {
+                        v1.deactivate();
+                    }
+                } else {
+                    context.destroy();
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    pub fn handle_v1_context_ev(&mut self, ev: ContextEvent) {
+        match ev {
+            ContextEvent::SurroundingText { text, cursor, .. } => {
+                self.set_surrounding_text(&text, cursor as usize);
+            }
+            ContextEvent::Reset => {
+                self.reset_by_client();
+            }
+            ContextEvent::ContentType { hint, purpose } => {
+                self.set_content_type(hint, purpose_from_v1(purpose));
+            }
+            ContextEvent::CommitState { serial } => {
+                if let InputMethod::V1(v1) = &mut self.im {
+                    v1.serial = serial;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn handle_v1_key_ev(&mut self, ev: KeyEvent) -> io::Result<()> {
+        match ev {
+            KeyEvent::Keymap { fd, format, size } => {
+                self.handle_keymap(format.to_raw(), fd, size);
+            }
+            KeyEvent::Key {
+                state, key, time, ..
+            } => {
+                self.handle_key(time, key, state)?;
+            }
+            KeyEvent::Modifiers {
+                mods_depressed,
+                mods_latched,
+                mods_locked,
+                group,
+                ..
+            } => {
+                self.handle_modifiers(mods_depressed, mods_latched, mods_locked, group);
+            }
+            KeyEvent::RepeatInfo { rate, delay } => {
+                self.handle_repeat_info(rate, delay);
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,102 @@
+use std::io;
+
+use wayland_client::event_enum;
+
+use zwp_input_method::input_method_unstable_v2::{
+    zwp_input_method_keyboard_grab_v2::{Event as KeyEvent, ZwpInputMethodKeyboardGrabV2},
+    zwp_input_method_v2::{Event as ImEvent, ZwpInputMethodV2},
+};
+
+use crate::context::KimeContext;
+use crate::input_method::InputMethod;
+
+event_enum! {
+    Events |
+    Key => ZwpInputMethodKeyboardGrabV2,
+    Im => ZwpInputMethodV2
+}
+
+impl KimeContext {
+    pub fn handle_im_ev(&mut self, ev: ImEvent) -> io::Result<()> {
+        match ev {
+            ImEvent::Activate => {
+                self.pending_state.activate = true;
+            }
+            ImEvent::Deactivate => {
+                self.pending_state.deactivate = true;
+            }
+            ImEvent::SurroundingText { text, cursor, .. } => {
+                self.pending_state.surrounding_text = Some((text, cursor as usize));
+            }
+            ImEvent::ContentType { hint, purpose } => {
+                self.pending_state.content_type = Some((hint.to_raw(), purpose.to_raw()));
+            }
+            ImEvent::TextChangeCause { cause } => {
+                // 0 is input_method, 1 is other
+                self.pending_state.external_change = cause.to_raw() != 0;
+            }
+            ImEvent::Done => {
+                if let InputMethod::V2(v2) = &mut self.im {
+                    v2.serial = v2.serial.wrapping_add(1);
+                }
+
+                if !self.current_state.activate && self.pending_state.activate {
+                    if let InputMethod::V2(v2) = &mut self.im {
+                        v2.grab_keyboard();
+                    }
+                    self.activate();
+                } else if !self.current_state.deactivate && self.pending_state.deactivate {
+                    self.deactivate()?;
+                    // Keys go to client directly while deactivated
+                    if let InputMethod::V2(v2) = &mut self.im {
+                        v2.release_keyboard();
+                    }
+                } else if self.pending_state.external_change {
+                    self.reset_by_client();
+                }
+
+                if let Some((hint, purpose)) = self.pending_state.content_type.take() {
+                    self.set_content_type(hint, purpose);
+                }
+
+                if let Some((text, cursor)) = self.pending_state.surrounding_text.take() {
+                    self.set_surrounding_text(&text, cursor);
+                }
+
+                self.current_state = std::mem::take(&mut self.pending_state);
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    pub fn handle_key_ev(&mut self, ev: KeyEvent) -> io::Result<()> {
+        match ev {
+            KeyEvent::Keymap { fd, format, size } => {
+                self.handle_keymap(format as u32, fd, size);
+            }
+            KeyEvent::Key {
+                state, key, time, ..
+            } => {
+                // NOTE: Never read `serial` of KeyEvent. Commit must use count of `done` events
+                self.handle_key(time, key, state)?;
+            }
+            KeyEvent::Modifiers {
+                mods_depressed,
+                mods_latched,
+                mods_locked,
+                group,
+                ..
+            } => {
+                self.handle_modifiers(mods_depressed, mods_latched, mods_locked, group);
+            }
+            KeyEvent::RepeatInfo { rate, delay } => {
+                self.handle_repeat_info(rate, delay);
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
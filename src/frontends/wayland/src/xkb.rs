@@ -0,0 +1,209 @@
+use std::io;
+use std::os::unix::io::RawFd;
+
+use xkbcommon::xkb;
+
+use kime_engine_cffi::{
+    ModifierState, ModifierState_ALT, ModifierState_CAPSLOCK, ModifierState_CONTROL,
+    ModifierState_NUMLOCK, ModifierState_SHIFT, ModifierState_SUPER,
+};
+
+/// Indices of modifiers used by engine
+#[derive(Clone, Copy)]
+struct ModIndices {
+    shift: xkb::ModIndex,
+    control: xkb::ModIndex,
+    alt: xkb::ModIndex,
+    super_: xkb::ModIndex,
+    caps_lock: xkb::ModIndex,
+    num_lock: xkb::ModIndex,
+}
+
+impl Default for ModIndices {
+    /// Indices of usual keymap, used until keymap is received
+    fn default() -> Self {
+        Self {
+            shift: 0,
+            caps_lock: 1,
+            control: 2,
+            alt: 3,
+            num_lock: 4,
+            super_: 6,
+        }
+    }
+}
+
+impl ModIndices {
+    fn from_keymap(keymap: &xkb::Keymap) -> Self {
+        // Prefer virtual modifiers so remapped modifiers are followed
+        let index = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| keymap.mod_get_index(*name))
+                .find(|index| *index != xkb::MOD_INVALID)
+                .unwrap_or(xkb::MOD_INVALID)
+        };
+
+        Self {
+            shift: index(&[xkb::MOD_NAME_SHIFT]),
+            control: index(&[xkb::MOD_NAME_CTRL]),
+            alt: index(&["Alt", xkb::MOD_NAME_ALT]),
+            super_: index(&["Super", xkb::MOD_NAME_LOGO]),
+            caps_lock: index(&[xkb::MOD_NAME_CAPS]),
+            num_lock: index(&["NumLock", xkb::MOD_NAME_NUM]),
+        }
+    }
+}
+
+/// Keymap and modifier state of keyboard grab
+pub struct Xkb {
+    context: xkb::Context,
+    /// `None` until valid keymap is received
+    state: Option<xkb::State>,
+    indices: ModIndices,
+    /// Format and text of last keymap, text is empty when format is not text
+    keymap: Option<(u32, String)>,
+}
+
+impl Xkb {
+    pub fn new() -> Self {
+        Self {
+            context: xkb::Context::new(xkb::CONTEXT_NO_FLAGS),
+            state: None,
+            indices: ModIndices::default(),
+            keymap: None,
+        }
+    }
+
+    /// Compile keymap from fd of wayland keymap event, fd is not closed
+    ///
+    /// Return `false` when keymap is same as last one
+    pub fn set_keymap(&mut self, format: u32, fd: RawFd, size: u32) -> io::Result<bool> {
+        if format != xkb::KEYMAP_FORMAT_TEXT_V1 {
+            self.state = None;
+            self.indices = ModIndices::default();
+            return Ok(self.replace_keymap(format, String::new()));
+        }
+
+        let size = size as usize;
+        let text = unsafe {
+            let ptr = libc::mmap(
+                std::ptr::null_mut(),
+                size,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                fd,
+                0,
+            );
+
+            if ptr == libc::MAP_FAILED {
+                return Err(io::Error::last_os_error());
+            }
+
+            let bytes = std::slice::from_raw_parts(ptr as *const u8, size);
+            // Keymap string is null terminated
+            let end = bytes.iter().position(|b| *b == 0).unwrap_or(size);
+            let text = String::from_utf8_lossy(&bytes[..end]).into_owned();
+            libc::munmap(ptr, size);
+            text
+        };
+
+        if !self.replace_keymap(format, text.clone()) {
+            return Ok(false);
+        }
+
+        let keymap = xkb::Keymap::new_from_string(
+            &self.context,
+            text,
+            xkb::KEYMAP_FORMAT_TEXT_V1,
+            xkb::KEYMAP_COMPILE_NO_FLAGS,
+        )
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid keymap"))?;
+
+        self.indices = ModIndices::from_keymap(&keymap);
+        self.state = Some(xkb::State::new(&keymap));
+
+        Ok(true)
+    }
+
+    /// Save last keymap, return `false` when it's not changed
+    fn replace_keymap(&mut self, format: u32, text: String) -> bool {
+        let keymap = Some((format, text));
+
+        if self.keymap == keymap {
+            false
+        } else {
+            self.keymap = keymap;
+            true
+        }
+    }
+
+    /// Mask of CapsLock in raw modifiers
+    pub fn caps_lock_mask(&self) -> u32 {
+        mask(self.indices.caps_lock)
+    }
+
+    /// Update state with raw modifiers and return modifiers used by engine
+    pub fn update_mask(
+        &mut self,
+        depressed: u32,
+        latched: u32,
+        locked: u32,
+        group: u32,
+    ) -> ModifierState {
+        if let Some(state) = self.state.as_mut() {
+            state.update_mask(depressed, latched, locked, 0, 0, group);
+        }
+
+        let indices = self.indices;
+        let state = self.state.as_ref();
+        let pressed = |index| match state {
+            Some(state) => {
+                index != xkb::MOD_INVALID
+                    && state.mod_index_is_active(
+                        index,
+                        xkb::STATE_MODS_DEPRESSED | xkb::STATE_MODS_LATCHED,
+                    )
+            }
+            None => depressed & mask(index) != 0,
+        };
+        let is_locked = |index| match state {
+            Some(state) => {
+                index != xkb::MOD_INVALID
+                    && state.mod_index_is_active(index, xkb::STATE_MODS_LOCKED)
+            }
+            None => locked & mask(index) != 0,
+        };
+
+        let mut mod_state = 0;
+
+        if pressed(indices.shift) {
+            mod_state |= ModifierState_SHIFT;
+        }
+        if pressed(indices.control) {
+            mod_state |= ModifierState_CONTROL;
+        }
+        if pressed(indices.alt) {
+            mod_state |= ModifierState_ALT;
+        }
+        if pressed(indices.super_) {
+            mod_state |= ModifierState_SUPER;
+        }
+        if is_locked(indices.caps_lock) {
+            mod_state |= ModifierState_CAPSLOCK;
+        }
+        if is_locked(indices.num_lock) {
+            mod_state |= ModifierState_NUMLOCK;
+        }
+
+        mod_state
+    }
+}
+
+fn mask(index: xkb::ModIndex) -> u32 {
+    if index < 32 {
+        1 << index
+    } else {
+        0
+    }
+}
@@ -0,0 +1,174 @@
+use std::num::NonZeroU32;
+
+use kime_engine_cffi::InputEngine;
+
+use x11rb::{
+    connection::Connection,
+    protocol::xproto::{ConfigureNotifyEvent, ConnectionExt as _, ExposeEvent, EXPOSE_EVENT},
+    xcb_ffi::XCBConnection,
+};
+use xim::x11rb::HasConnection;
+
+use crate::pe_window::{create_popup_window, find_position};
+use crate::style::{resize_window, set_source_color, Style};
+
+/// Candidate list shown below preedit spot
+pub struct CandidateWindow {
+    window: NonZeroU32,
+    surface: cairo::XCBSurface,
+    cr: cairo::Context,
+    layout: pango::Layout,
+    lines: Vec<String>,
+    selected: usize,
+    size: (u16, u16),
+}
+
+impl CandidateWindow {
+    pub fn new(
+        conn: &XCBConnection,
+        style: &Style,
+        app_win: Option<NonZeroU32>,
+        spot_location: xim::Point,
+        screen_num: usize,
+    ) -> Result<Self, xim::ServerError> {
+        let screen = &conn.setup().roots[screen_num];
+        let (x, y) = find_position(conn, screen.root, app_win, spot_location)?;
+        // Place below preedit window
+        let pos = (x, y.saturating_add(style.preedit_height() as i16));
+        let size = (1, 1);
+        let (window, surface) = create_popup_window(conn, screen_num, pos, size)?;
+
+        let cr = cairo::Context::new(&surface);
+        let layout = style.create_layout(&cr);
+
+        Ok(Self {
+            window,
+            surface,
+            cr,
+            layout,
+            lines: Vec::new(),
+            selected: 0,
+            size,
+        })
+    }
+
+    pub fn clean<C: HasConnection>(self, c: C) -> Result<(), xim::ServerError> {
+        let conn = c.conn();
+        self.surface.finish();
+        conn.destroy_window(self.window.get())?.ignore_error();
+        conn.flush()?;
+
+        Ok(())
+    }
+
+    pub fn window(&self) -> NonZeroU32 {
+        self.window
+    }
+
+    /// Pixel size of each line
+    fn line_sizes(&self) -> Vec<(i32, i32)> {
+        self.lines
+            .iter()
+            .map(|line| {
+                self.layout.set_text(line);
+                self.layout.get_pixel_size()
+            })
+            .collect()
+    }
+
+    fn redraw(&mut self, style: &Style) {
+        let inset = style.inset();
+        let theme = style.theme();
+        let mut top = inset;
+
+        style.draw_frame(&self.cr, self.size);
+
+        for (index, (line, (_, height))) in self.lines.iter().zip(self.line_sizes()).enumerate() {
+            let height = height as f64;
+
+            if index == self.selected {
+                set_source_color(&self.cr, theme.highlight);
+                self.cr
+                    .rectangle(inset, top, self.size.0 as f64 - inset * 2.0, height);
+                self.cr.fill();
+            }
+
+            self.layout.set_text(line);
+            set_source_color(&self.cr, theme.foreground);
+            self.cr.move_to(inset, top);
+            pangocairo::functions::show_layout(&self.cr, &self.layout);
+
+            top += height;
+        }
+
+        self.surface.flush();
+    }
+
+    pub fn expose(&mut self, style: &Style) {
+        self.redraw(style);
+    }
+
+    pub fn configure_notify(&mut self, e: ConfigureNotifyEvent, style: &Style) {
+        self.size = (e.width, e.height);
+        self.surface.set_size(e.width as _, e.height as _).unwrap();
+        self.redraw(style);
+    }
+
+    /// Show current page of candidate list with numbering, window is resized to fit it
+    pub fn set_candidates(
+        &mut self,
+        conn: &XCBConnection,
+        style: &Style,
+        engine: &InputEngine,
+    ) -> Result<(), xim::ServerError> {
+        let page = engine.candidate_page();
+
+        self.lines.clear();
+        for index in 0..page.len {
+            let (value, comment) = engine.candidate(index);
+            let line = if comment.is_empty() {
+                format!("{}. {}", index + 1, value)
+            } else {
+                format!("{}. {} {}", index + 1, value, comment)
+            };
+            self.lines.push(line);
+        }
+
+        if page.page_count > 1 {
+            self.lines
+                .push(format!("({}/{})", page.page + 1, page.page_count));
+        }
+
+        self.selected = page.selected;
+
+        let text_size = self
+            .line_sizes()
+            .into_iter()
+            .fold((0, 0), |(width, height), (w, h)| (width.max(w), height + h));
+        let size = style.window_size(text_size);
+        resize_window(conn, self.window, &self.surface, &mut self.size, size)?;
+
+        self.refresh(conn)
+    }
+
+    fn refresh(&self, conn: &XCBConnection) -> Result<(), xim::ServerError> {
+        conn.send_event(
+            false,
+            self.window.get(),
+            0u32,
+            ExposeEvent {
+                response_type: EXPOSE_EVENT,
+                window: self.window.get(),
+                width: 0,
+                height: 0,
+                x: 0,
+                y: 0,
+                sequence: 0,
+                count: 0,
+            },
+        )?;
+        conn.flush()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,68 @@
+use std::ops::Range;
+
+use xim::Feedback;
+
+use kime_engine_cffi::{
+    PreeditSegment, PreeditStyle, PreeditStyle_Composing, PreeditStyle_Hint,
+    PreeditStyle_SelectedCandidate, PreeditStyle_Word,
+};
+
+// XIMFeedback values
+pub const XIM_REVERSE: u32 = 1;
+pub const XIM_UNDERLINE: u32 = 1 << 1;
+pub const XIM_HIGHLIGHT: u32 = 1 << 2;
+
+fn style_feedback(style: PreeditStyle) -> u32 {
+    match style {
+        PreeditStyle_Word => XIM_UNDERLINE,
+        PreeditStyle_Composing => XIM_REVERSE,
+        PreeditStyle_Hint => XIM_HIGHLIGHT,
+        // Same color as selected row of candidate window
+        PreeditStyle_SelectedCandidate => XIM_HIGHLIGHT,
+        _ => 0,
+    }
+}
+
+/// Convert preedit segments into byte ranges with XIMFeedback which cover whole preedit
+pub fn preedit_feedbacks(
+    preedit_len: usize,
+    segments: &[PreeditSegment],
+    out: &mut Vec<(Range<usize>, u32)>,
+) {
+    out.clear();
+
+    let mut pos = 0;
+
+    for segment in segments {
+        if pos < segment.start {
+            out.push((pos..segment.start, 0));
+        }
+        out.push((segment.start..segment.end, style_feedback(segment.style)));
+        pos = segment.end;
+    }
+
+    if pos < preedit_len {
+        out.push((pos..preedit_len, 0));
+    }
+}
+
+/// XIMFeedback of each character for preedit draw callback
+///
+/// Plain text is underlined, so client can tell preedit from committed text
+pub fn char_feedbacks(preedit: &str, segments: &[PreeditSegment]) -> Vec<Feedback> {
+    preedit
+        .char_indices()
+        .map(|(pos, _)| {
+            let feedback = segments
+                .iter()
+                .find(|segment| segment.start <= pos && pos < segment.end)
+                .map_or(0, |segment| style_feedback(segment.style));
+
+            match feedback {
+                XIM_REVERSE => Feedback::Reverse,
+                XIM_HIGHLIGHT => Feedback::Highlight,
+                _ => Feedback::Underline,
+            }
+        })
+        .collect()
+}
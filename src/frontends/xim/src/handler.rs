@@ -1,48 +1,101 @@
 use std::num::NonZeroU32;
+use std::time::{Duration, Instant};
 
-use crate::pe_window::PeWindow;
+use crate::candidate_window::CandidateWindow;
+use crate::feedback::char_feedbacks;
+use crate::indicator_window::IndicatorWindow;
+use crate::pe_window::{find_position, PeWindow};
+use crate::style::Style;
 use ahash::AHashMap;
 use x11rb::{
-    protocol::xproto::{ConfigureNotifyEvent, KeyPressEvent, KEY_PRESS_EVENT},
+    connection::Connection,
+    protocol::xproto::{
+        AtomEnum, ConfigureNotifyEvent, ConnectionExt, KeyPressEvent, KEY_PRESS_EVENT,
+        KEY_RELEASE_EVENT,
+    },
     xcb_ffi::XCBConnection,
 };
 use xim::{
     x11rb::{HasConnection, X11rbServer},
-    InputStyle, Server, ServerHandler,
+    CaretDirection, CaretStyle, ForwardEventFlag, InputStyle, PreeditDrawStatus, Request, Server,
+    ServerCore, ServerHandler,
 };
 
 use kime_engine_cffi::{
-    Config, InputEngine, InputResult_CONSUMED, InputResult_HAS_PREEDIT,
-    InputResult_LANGUAGE_CHANGED, InputResult_NEED_FLUSH, InputResult_NEED_RESET,
-    ModifierState_ALT, ModifierState_CONTROL, ModifierState_SHIFT, ModifierState_SUPER,
+    Config, InputEngine, InputResult, InputResult_CONSUMED, InputResult_HAS_CANDIDATE,
+    InputResult_HAS_PREEDIT, InputResult_LANGUAGE_CHANGED, InputResult_NEED_FLUSH,
+    InputResult_NEED_RESET, InputResult_REPLAY_SEQUENCE, InputResult_SEQUENCE_PENDING,
+    ModifierState, ModifierState_ALT, ModifierState_CAPSLOCK, ModifierState_CONTROL,
+    ModifierState_NUMLOCK, ModifierState_SHIFT, ModifierState_SUPER,
 };
 
 pub struct KimeData {
     engine: InputEngine,
     pe: Option<NonZeroU32>,
     show_preedit_window: bool,
+    /// Client draws preedit by callbacks (on-the-spot)
+    preedit_callbacks: bool,
+    /// Preedit and caret in characters drawn by callbacks, `None` until `PreeditStart` is sent
+    drawn_preedit: Option<(String, usize)>,
+    /// Window showing candidate list
+    candidate: Option<NonZeroU32>,
+    /// Show indicator when input mode is changed
+    mode_indicator: bool,
+    /// Client has status area (off-the-spot), indicator is kept there while focused
+    status_area: bool,
+    /// Key events consumed by pending hotkey sequence
+    sequence_events: Vec<KeyPressEvent>,
 }
 
 impl KimeData {
-    pub fn new(config: &Config, show_preedit_window: bool) -> Self {
+    pub fn new(
+        config: &Config,
+        show_preedit_window: bool,
+        preedit_callbacks: bool,
+        candidate_window: bool,
+        mode_indicator: bool,
+        status_area: bool,
+    ) -> Self {
+        let mut engine = InputEngine::new(config);
+        engine.set_candidate_support(candidate_window);
+
         Self {
-            engine: InputEngine::new(config),
+            engine,
             pe: None,
             show_preedit_window,
+            preedit_callbacks,
+            drawn_preedit: None,
+            candidate: None,
+            mode_indicator,
+            status_area,
+            sequence_events: Vec::new(),
         }
     }
 }
 
 pub struct KimeHandler {
     preedit_windows: AHashMap<NonZeroU32, PeWindow>,
+    candidate_windows: AHashMap<NonZeroU32, CandidateWindow>,
+    /// Only indicator of focused input context is shown
+    indicator: Option<IndicatorWindow>,
+    /// Input method and input context id which indicator is shown for
+    indicator_owner: Option<(u16, u16)>,
+    /// When transient indicator is hidden
+    indicator_deadline: Option<Instant>,
+    style: Style,
     config: Config,
     screen_num: usize,
 }
 
 impl KimeHandler {
-    pub fn new(screen_num: usize, config: Config) -> Self {
+    pub fn new(conn: &XCBConnection, screen_num: usize, config: Config) -> Self {
         Self {
             preedit_windows: AHashMap::new(),
+            candidate_windows: AHashMap::new(),
+            indicator: None,
+            indicator_owner: None,
+            indicator_deadline: None,
+            style: Style::new(conn, screen_num, &config),
             config,
             screen_num,
         }
@@ -53,7 +106,11 @@ impl KimeHandler {
     pub fn expose(&mut self, window: u32) {
         if let Some(win) = NonZeroU32::new(window) {
             if let Some(pe) = self.preedit_windows.get_mut(&win) {
-                pe.expose();
+                pe.expose(&self.style);
+            } else if let Some(cw) = self.candidate_windows.get_mut(&win) {
+                cw.expose(&self.style);
+            } else if let Some(indicator) = self.indicator.as_mut().filter(|i| i.window() == win) {
+                indicator.expose(&self.style);
             }
         }
     }
@@ -61,16 +118,111 @@ impl KimeHandler {
     pub fn configure_notify(&mut self, e: ConfigureNotifyEvent) {
         if let Some(win) = NonZeroU32::new(e.window) {
             if let Some(pe) = self.preedit_windows.get_mut(&win) {
-                pe.configure_notify(e);
+                pe.configure_notify(e, &self.style);
+            } else if let Some(cw) = self.candidate_windows.get_mut(&win) {
+                cw.configure_notify(e, &self.style);
+            } else if let Some(indicator) = self.indicator.as_mut().filter(|i| i.window() == win) {
+                indicator.configure_notify(e, &self.style);
             }
         }
     }
 
+    /// Time left until transient indicator is hidden
+    pub fn indicator_timeout(&self) -> Option<Duration> {
+        self.indicator_deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    pub fn handle_indicator_timeout(
+        &mut self,
+        conn: &XCBConnection,
+    ) -> Result<(), xim::ServerError> {
+        if self
+            .indicator_deadline
+            .map_or(false, |deadline| deadline <= Instant::now())
+        {
+            self.hide_indicator(conn)?;
+        }
+
+        Ok(())
+    }
+
+    fn show_indicator(
+        &mut self,
+        server: &mut X11rbServer<XCBConnection>,
+        user_ic: &mut xim::UserInputContext<KimeData>,
+    ) -> Result<(), xim::ServerError> {
+        if !user_ic.user_data.mode_indicator {
+            return Ok(());
+        }
+
+        self.hide_indicator(server.conn())?;
+
+        let conn = server.conn();
+        let root = conn.setup().roots[self.screen_num].root;
+        let pos = if user_ic.user_data.status_area {
+            // xim doesn't tell the status area of client, use bottom left corner of focus window
+            // where clients usually put it
+            let focus_win = user_ic.ic.app_focus_win().or_else(|| user_ic.ic.app_win());
+            bottom_left(conn, root, focus_win)?
+        } else {
+            find_position(conn, root, user_ic.ic.app_win(), user_ic.ic.preedit_spot())?
+        };
+
+        let mut indicator = IndicatorWindow::new(conn, &self.style, pos, self.screen_num)?;
+        indicator.set_mode(
+            conn,
+            &self.style,
+            user_ic.user_data.engine.is_hangul_enabled(),
+        )?;
+
+        if user_ic.user_data.status_area {
+            let height = indicator.size().1 as i16;
+            indicator.move_to(conn, (pos.0, pos.1.saturating_sub(height)))?;
+        } else {
+            let duration = Duration::from_millis(self.config.mode_indicator().duration);
+            self.indicator_deadline = Some(Instant::now() + duration);
+        }
+
+        self.indicator = Some(indicator);
+        self.indicator_owner = Some(ic_id(user_ic));
+
+        Ok(())
+    }
+
+    fn hide_indicator(&mut self, conn: &XCBConnection) -> Result<(), xim::ServerError> {
+        self.indicator_deadline = None;
+        self.indicator_owner = None;
+
+        if let Some(indicator) = self.indicator.take() {
+            indicator.clean(conn)?;
+        }
+
+        Ok(())
+    }
+
+    /// Indicator is shared by all input contexts, don't hide one shown for other input context
+    fn hide_indicator_of(
+        &mut self,
+        conn: &XCBConnection,
+        user_ic: &xim::UserInputContext<KimeData>,
+    ) -> Result<(), xim::ServerError> {
+        if self.indicator_owner == Some(ic_id(user_ic)) {
+            self.hide_indicator(conn)?;
+        }
+
+        Ok(())
+    }
+
     fn preedit(
         &mut self,
         server: &mut X11rbServer<XCBConnection>,
         user_ic: &mut xim::UserInputContext<KimeData>,
     ) -> Result<(), xim::ServerError> {
+        if user_ic.user_data.preedit_callbacks {
+            return self.draw_preedit_callbacks(server, user_ic);
+        }
+
         if !user_ic.user_data.show_preedit_window {
             return Ok(());
         }
@@ -82,19 +234,18 @@ impl KimeHandler {
         if let Some(pe) = user_ic.user_data.pe.as_mut() {
             // Draw in server (already have pe_window)
             let pe = self.preedit_windows.get_mut(pe).unwrap();
-            pe.set_preedit(user_ic.user_data.engine.preedit_str());
-            pe.refresh(server.conn())?;
+            pe.set_preedit(server.conn(), &self.style, &user_ic.user_data.engine)?;
         } else {
             // Draw in server
             let mut pe = PeWindow::new(
                 server.conn(),
-                self.config.xim_font(),
+                &self.style,
                 user_ic.ic.app_win(),
                 user_ic.ic.preedit_spot(),
                 self.screen_num,
             )?;
 
-            pe.set_preedit(user_ic.user_data.engine.preedit_str());
+            pe.set_preedit(server.conn(), &self.style, &user_ic.user_data.engine)?;
             user_ic.user_data.pe = Some(pe.window());
 
             self.preedit_windows.insert(pe.window(), pe);
@@ -103,6 +254,153 @@ impl KimeHandler {
         Ok(())
     }
 
+    /// Replace whole preedit drawn by callbacks, only move caret when preedit is not changed
+    fn draw_preedit_callbacks(
+        &mut self,
+        server: &mut X11rbServer<XCBConnection>,
+        user_ic: &mut xim::UserInputContext<KimeData>,
+    ) -> Result<(), xim::ServerError> {
+        let data = &mut user_ic.user_data;
+        let preedit = data.engine.preedit_str();
+
+        if preedit.is_empty() {
+            return self.done_preedit_callbacks(server, user_ic);
+        }
+
+        let input_method_id = user_ic.ic.input_method_id().get();
+        let input_context_id = user_ic.ic.input_context_id().get();
+        let client_win = user_ic.ic.client_win();
+
+        let cursor = data.engine.preedit_cursor().min(preedit.len());
+        let caret = preedit[..cursor].chars().count();
+
+        match data.drawn_preedit.as_ref() {
+            Some((drawn, drawn_caret)) if drawn == preedit => {
+                if *drawn_caret != caret {
+                    server.send_req(
+                        client_win,
+                        Request::PreeditCaret {
+                            input_method_id,
+                            input_context_id,
+                            position: caret as i32,
+                            direction: CaretDirection::AbsolutePosition,
+                            style: CaretStyle::Primary,
+                        },
+                    )?;
+                }
+            }
+            drawn => {
+                let chg_length = match drawn {
+                    Some((drawn, _)) => drawn.chars().count(),
+                    None => {
+                        server.send_req(
+                            client_win,
+                            Request::PreeditStart {
+                                input_method_id,
+                                input_context_id,
+                            },
+                        )?;
+                        0
+                    }
+                };
+
+                server.send_req(
+                    client_win,
+                    Request::PreeditDraw {
+                        input_method_id,
+                        input_context_id,
+                        caret: caret as i32,
+                        chg_first: 0,
+                        chg_length: chg_length as i32,
+                        status: PreeditDrawStatus::empty(),
+                        preedit_string: xim_ctext::utf8_to_compound_text(preedit),
+                        feedbacks: char_feedbacks(preedit, data.engine.preedit_segments()),
+                    },
+                )?;
+            }
+        }
+
+        data.drawn_preedit = Some((preedit.into(), caret));
+
+        Ok(())
+    }
+
+    /// Erase preedit drawn by callbacks and finish preedit
+    fn done_preedit_callbacks(
+        &mut self,
+        server: &mut X11rbServer<XCBConnection>,
+        user_ic: &mut xim::UserInputContext<KimeData>,
+    ) -> Result<(), xim::ServerError> {
+        if let Some((drawn, _)) = user_ic.user_data.drawn_preedit.take() {
+            let input_method_id = user_ic.ic.input_method_id().get();
+            let input_context_id = user_ic.ic.input_context_id().get();
+
+            server.send_req(
+                user_ic.ic.client_win(),
+                Request::PreeditDraw {
+                    input_method_id,
+                    input_context_id,
+                    caret: 0,
+                    chg_first: 0,
+                    chg_length: drawn.chars().count() as i32,
+                    status: PreeditDrawStatus::NO_STRING | PreeditDrawStatus::NO_FEEDBACK,
+                    preedit_string: Vec::new(),
+                    feedbacks: Vec::new(),
+                },
+            )?;
+            server.send_req(
+                user_ic.ic.client_win(),
+                Request::PreeditDone {
+                    input_method_id,
+                    input_context_id,
+                },
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn show_candidates(
+        &mut self,
+        server: &mut X11rbServer<XCBConnection>,
+        user_ic: &mut xim::UserInputContext<KimeData>,
+    ) -> Result<(), xim::ServerError> {
+        if let Some(cw) = user_ic.user_data.candidate {
+            let cw = self.candidate_windows.get_mut(&cw).unwrap();
+            cw.set_candidates(server.conn(), &self.style, &user_ic.user_data.engine)?;
+        } else {
+            let mut cw = CandidateWindow::new(
+                server.conn(),
+                &self.style,
+                user_ic.ic.app_win(),
+                user_ic.ic.preedit_spot(),
+                self.screen_num,
+            )?;
+
+            cw.set_candidates(server.conn(), &self.style, &user_ic.user_data.engine)?;
+            user_ic.user_data.candidate = Some(cw.window());
+
+            self.candidate_windows.insert(cw.window(), cw);
+        }
+
+        Ok(())
+    }
+
+    fn hide_candidates(
+        &mut self,
+        server: &mut X11rbServer<XCBConnection>,
+        user_ic: &mut xim::UserInputContext<KimeData>,
+    ) -> Result<(), xim::ServerError> {
+        if let Some(cw) = user_ic.user_data.candidate.take() {
+            if let Some(w) = self.candidate_windows.remove(&cw) {
+                log::trace!("Destory CandidateWindow: {}", w.window());
+                w.clean(server.conn())?;
+            }
+        }
+
+        Ok(())
+    }
+
     fn reset(
         &mut self,
         server: &mut X11rbServer<XCBConnection>,
@@ -111,9 +409,11 @@ impl KimeHandler {
         user_ic.user_data.engine.clear_preedit();
 
         self.clear_preedit(server, user_ic)?;
+        self.hide_candidates(server, user_ic)?;
         self.commit(server, user_ic)?;
 
         user_ic.user_data.engine.reset();
+        user_ic.user_data.sequence_events.clear();
 
         Ok(())
     }
@@ -123,6 +423,8 @@ impl KimeHandler {
         server: &mut X11rbServer<XCBConnection>,
         user_ic: &mut xim::UserInputContext<KimeData>,
     ) -> Result<(), xim::ServerError> {
+        self.done_preedit_callbacks(server, user_ic)?;
+
         if let Some(pe) = user_ic.user_data.pe.take() {
             // off-the-spot draw in server
             if let Some(w) = self.preedit_windows.remove(&pe) {
@@ -134,6 +436,87 @@ impl KimeHandler {
         Ok(())
     }
 
+    fn process_input_result(
+        &mut self,
+        server: &mut X11rbServer<XCBConnection>,
+        user_ic: &mut xim::UserInputContext<KimeData>,
+        ret: InputResult,
+    ) -> Result<(), xim::ServerError> {
+        if ret & InputResult_LANGUAGE_CHANGED != 0 {
+            user_ic.user_data.engine.update_hangul_state();
+        }
+
+        if ret & InputResult_HAS_PREEDIT != 0 {
+            self.preedit(server, user_ic)?;
+        } else {
+            self.clear_preedit(server, user_ic)?;
+        }
+
+        if ret & InputResult_HAS_CANDIDATE != 0 {
+            self.show_candidates(server, user_ic)?;
+        } else {
+            self.hide_candidates(server, user_ic)?;
+
+            if ret & InputResult_LANGUAGE_CHANGED != 0 {
+                self.show_indicator(server, user_ic)?;
+            }
+        }
+
+        if ret & (InputResult_NEED_RESET | InputResult_NEED_FLUSH) != 0 {
+            self.commit(server, user_ic)?;
+
+            if ret & InputResult_NEED_RESET != 0 {
+                user_ic.user_data.engine.reset();
+            } else {
+                user_ic.user_data.engine.flush();
+            }
+        }
+
+        if ret & InputResult_REPLAY_SEQUENCE != 0 {
+            self.replay_sequence(server, user_ic)?;
+        }
+
+        Ok(())
+    }
+
+    /// Send key events of unmatched hotkey sequence to client
+    fn replay_sequence(
+        &mut self,
+        server: &mut X11rbServer<XCBConnection>,
+        user_ic: &mut xim::UserInputContext<KimeData>,
+    ) -> Result<(), xim::ServerError> {
+        for xev in user_ic.user_data.sequence_events.drain(..) {
+            for response_type in [KEY_PRESS_EVENT, KEY_RELEASE_EVENT].iter().copied() {
+                server.send_req(
+                    user_ic.ic.client_win(),
+                    Request::ForwardEvent {
+                        input_method_id: user_ic.ic.input_method_id().get(),
+                        input_context_id: user_ic.ic.input_context_id().get(),
+                        flag: ForwardEventFlag::empty(),
+                        serial_number: xev.sequence,
+                        xev: xim::XEvent {
+                            response_type,
+                            detail: xev.detail,
+                            sequence: xev.sequence,
+                            time: xev.time,
+                            root: xev.root,
+                            event: xev.event,
+                            child: xev.child,
+                            root_x: xev.root_x,
+                            root_y: xev.root_y,
+                            event_x: xev.event_x,
+                            event_y: xev.event_y,
+                            state: xev.state,
+                            same_screen: xev.same_screen,
+                        },
+                    },
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
     fn commit(
         &mut self,
         server: &mut X11rbServer<XCBConnection>,
@@ -148,7 +531,7 @@ impl KimeHandler {
 }
 
 impl ServerHandler<X11rbServer<XCBConnection>> for KimeHandler {
-    type InputStyleArray = [InputStyle; 3];
+    type InputStyleArray = Vec<InputStyle>;
     type InputContextData = KimeData;
 
     fn new_ic_data(
@@ -156,36 +539,57 @@ impl ServerHandler<X11rbServer<XCBConnection>> for KimeHandler {
         _server: &mut X11rbServer<XCBConnection>,
         input_style: InputStyle,
     ) -> Result<Self::InputContextData, xim::ServerError> {
-        let mut show_preedit_window = true;
-
         // Use callback instead
-        if input_style.contains(InputStyle::PREEDIT_CALLBACKS) {
-            show_preedit_window = false;
-        }
+        let preedit_callbacks = input_style.contains(InputStyle::PREEDIT_CALLBACKS);
 
-        // Don't show preedit window on Xwayland see #137
-        if std::env::var("XDG_SESSION_TYPE")
+        // Don't show windows on Xwayland see #137
+        let xwayland = std::env::var("XDG_SESSION_TYPE")
             .map(|v| v == "wayland")
-            .unwrap_or(false)
-        {
-            show_preedit_window = false;
-        }
+            .unwrap_or(false);
+
+        let show_preedit_window = !preedit_callbacks && !xwayland;
+        let candidate_window = !xwayland;
+        let mode_indicator = self.config.mode_indicator().enable && !xwayland;
+        let status_area = input_style.contains(InputStyle::STATUS_AREA);
 
-        Ok(KimeData::new(&self.config, show_preedit_window))
+        Ok(KimeData::new(
+            &self.config,
+            show_preedit_window,
+            preedit_callbacks,
+            candidate_window,
+            mode_indicator,
+            status_area,
+        ))
     }
 
     fn input_styles(&self) -> Self::InputStyleArray {
-        [
+        let mut styles = vec![
             // over-spot
             InputStyle::PREEDIT_NOTHING | InputStyle::STATUS_NOTHING,
             InputStyle::PREEDIT_POSITION | InputStyle::STATUS_NOTHING,
             InputStyle::PREEDIT_POSITION | InputStyle::STATUS_NONE,
-            // // on-the-spot when enable this java awt doesn't work I don't know why
-        ]
+            // input mode is shown in status area (off-the-spot)
+            InputStyle::PREEDIT_NOTHING | InputStyle::STATUS_AREA,
+            InputStyle::PREEDIT_POSITION | InputStyle::STATUS_AREA,
+        ];
+
+        // on-the-spot when enable this java awt doesn't work I don't know why
+        // so preedit is drawn by client only when user opted in
+        if self.config.xim_preedit_callbacks() {
+            styles.extend_from_slice(&[
+                InputStyle::PREEDIT_CALLBACKS | InputStyle::STATUS_NOTHING,
+                InputStyle::PREEDIT_CALLBACKS | InputStyle::STATUS_NONE,
+                InputStyle::PREEDIT_CALLBACKS | InputStyle::STATUS_AREA,
+            ]);
+        }
+
+        styles
     }
 
     fn filter_events(&self) -> u32 {
-        x11rb::protocol::xproto::EventMask::KEY_PRESS.into()
+        (x11rb::protocol::xproto::EventMask::KEY_PRESS
+            | x11rb::protocol::xproto::EventMask::KEY_RELEASE)
+            .into()
     }
 
     fn handle_connect(
@@ -202,8 +606,11 @@ impl ServerHandler<X11rbServer<XCBConnection>> for KimeHandler {
     ) -> Result<(), xim::ServerError> {
         log::debug!("spot: {:?}", user_ic.ic.preedit_spot());
 
-        self.clear_preedit(server, user_ic)?;
-        self.preedit(server, user_ic)?;
+        // Move preedit window to new spot, client places preedit of callbacks by itself
+        if !user_ic.user_data.preedit_callbacks {
+            self.clear_preedit(server, user_ic)?;
+            self.preedit(server, user_ic)?;
+        }
 
         Ok(())
     }
@@ -219,7 +626,17 @@ impl ServerHandler<X11rbServer<XCBConnection>> for KimeHandler {
             user_ic.ic.preedit_spot()
         );
 
-        server.set_event_mask(&user_ic.ic, 1, 0)?;
+        // KeyPress, KeyRelease
+        server.set_event_mask(&user_ic.ic, 0b11, 0)?;
+
+        let app = std::iter::once(user_ic.ic.client_win())
+            .chain(user_ic.ic.app_win().map(NonZeroU32::get))
+            .find_map(|win| wm_class_instance(server.conn(), win).ok().flatten());
+
+        if let Some(app) = app {
+            log::debug!("app: {}", app);
+            user_ic.user_data.engine.set_app(&app);
+        }
 
         Ok(())
     }
@@ -239,29 +656,24 @@ impl ServerHandler<X11rbServer<XCBConnection>> for KimeHandler {
         user_ic: &mut xim::UserInputContext<Self::InputContextData>,
         xev: &KeyPressEvent,
     ) -> Result<bool, xim::ServerError> {
-        // skip release
-        if xev.response_type != KEY_PRESS_EVENT {
-            return Ok(false);
-        }
-
         log::trace!("{:?}", xev);
 
-        let mut state = 0;
+        let state = modifier_state(xev.state);
 
-        if xev.state & 0x1 != 0 {
-            state |= ModifierState_SHIFT;
-        }
-
-        if xev.state & 0x4 != 0 {
-            state |= ModifierState_CONTROL;
-        }
-
-        if xev.state & 0x8 != 0 {
-            state |= ModifierState_ALT;
-        }
+        if xev.response_type != KEY_PRESS_EVENT {
+            let ret = user_ic
+                .user_data
+                .engine
+                .release_key(&self.config, xev.detail as u16, state);
+
+            // Tap hotkey is processed
+            if ret != 0 {
+                log::trace!("{:?}", ret);
+                self.process_input_result(server, user_ic, ret)?;
+            }
 
-        if xev.state & 0x40 != 0 {
-            state |= ModifierState_SUPER;
+            // Release event must be delivered to client
+            return Ok(false);
         }
 
         let ret = user_ic
@@ -271,24 +683,13 @@ impl ServerHandler<X11rbServer<XCBConnection>> for KimeHandler {
 
         log::trace!("{:?}", ret);
 
-        if ret & InputResult_LANGUAGE_CHANGED != 0 {
-            user_ic.user_data.engine.update_hangul_state();
-        }
+        self.process_input_result(server, user_ic, ret)?;
 
-        if ret & InputResult_HAS_PREEDIT != 0 {
-            self.preedit(server, user_ic)?;
+        if ret & InputResult_SEQUENCE_PENDING != 0 {
+            // Keep event to replay when sequence is not matched
+            user_ic.user_data.sequence_events.push(*xev);
         } else {
-            self.clear_preedit(server, user_ic)?;
-        }
-
-        if ret & InputResult_NEED_RESET | InputResult_NEED_FLUSH != 0 {
-            self.commit(server, user_ic)?;
-
-            if ret & InputResult_NEED_RESET != 0 {
-                user_ic.user_data.engine.reset();
-            } else {
-                user_ic.user_data.engine.flush();
-            }
+            user_ic.user_data.sequence_events.clear();
         }
 
         Ok(ret & InputResult_CONSUMED != 0)
@@ -305,6 +706,15 @@ impl ServerHandler<X11rbServer<XCBConnection>> for KimeHandler {
             self.preedit_windows.remove(&pe).unwrap().clean(&*server)?;
         }
 
+        if let Some(cw) = user_ic.user_data.candidate {
+            self.candidate_windows
+                .remove(&cw)
+                .unwrap()
+                .clean(&*server)?;
+        }
+
+        self.hide_indicator_of(server.conn(), &user_ic)?;
+
         Ok(())
     }
 
@@ -327,11 +737,17 @@ impl ServerHandler<X11rbServer<XCBConnection>> for KimeHandler {
 
     fn handle_set_focus(
         &mut self,
-        _server: &mut X11rbServer<XCBConnection>,
+        server: &mut X11rbServer<XCBConnection>,
         user_ic: &mut xim::UserInputContext<Self::InputContextData>,
     ) -> Result<(), xim::ServerError> {
         user_ic.user_data.engine.update_hangul_state();
-        Ok(())
+
+        if user_ic.user_data.status_area {
+            self.show_indicator(server, user_ic)?;
+        }
+
+        // Show preedit kept by focus out policy
+        self.preedit(server, user_ic)
     }
 
     fn handle_unset_focus(
@@ -339,6 +755,92 @@ impl ServerHandler<X11rbServer<XCBConnection>> for KimeHandler {
         server: &mut X11rbServer<XCBConnection>,
         user_ic: &mut xim::UserInputContext<Self::InputContextData>,
     ) -> Result<(), xim::ServerError> {
-        self.reset(server, user_ic)
+        let ret = user_ic.user_data.engine.focus_out(&self.config);
+
+        self.clear_preedit(server, user_ic)?;
+        self.hide_candidates(server, user_ic)?;
+        self.hide_indicator_of(server.conn(), user_ic)?;
+        user_ic.user_data.sequence_events.clear();
+
+        if ret & InputResult_NEED_RESET != 0 {
+            self.commit(server, user_ic)?;
+            user_ic.user_data.engine.reset();
+        }
+
+        Ok(())
+    }
+}
+
+/// Input context id is only unique in its input method
+fn ic_id(user_ic: &xim::UserInputContext<KimeData>) -> (u16, u16) {
+    (
+        user_ic.ic.input_method_id().get(),
+        user_ic.ic.input_context_id().get(),
+    )
+}
+
+/// Bottom left corner of window in root coordinates
+fn bottom_left(
+    conn: &XCBConnection,
+    root: u32,
+    window: Option<NonZeroU32>,
+) -> Result<(i16, i16), xim::ServerError> {
+    match window {
+        Some(window) => {
+            let geometry = conn.get_geometry(window.get())?.reply()?;
+            let offset = conn
+                .translate_coordinates(window.get(), root, 0, geometry.height as i16)?
+                .reply()?;
+
+            Ok((offset.dst_x, offset.dst_y))
+        }
+        None => Ok((0, 0)),
     }
 }
+
+/// First part of `WM_CLASS` property which is usually program name
+fn wm_class_instance(
+    conn: &XCBConnection,
+    window: u32,
+) -> Result<Option<String>, xim::ServerError> {
+    let reply = conn
+        .get_property(false, window, AtomEnum::WM_CLASS, AtomEnum::STRING, 0, 256)?
+        .reply()?;
+
+    Ok(reply
+        .value
+        .split(|&b| b == 0)
+        .next()
+        .filter(|instance| !instance.is_empty())
+        .map(|instance| String::from_utf8_lossy(instance).into_owned()))
+}
+
+fn modifier_state(x_state: u16) -> ModifierState {
+    let mut state = 0;
+
+    if x_state & 0x1 != 0 {
+        state |= ModifierState_SHIFT;
+    }
+
+    if x_state & 0x4 != 0 {
+        state |= ModifierState_CONTROL;
+    }
+
+    if x_state & 0x8 != 0 {
+        state |= ModifierState_ALT;
+    }
+
+    if x_state & 0x40 != 0 {
+        state |= ModifierState_SUPER;
+    }
+
+    if x_state & 0x2 != 0 {
+        state |= ModifierState_CAPSLOCK;
+    }
+
+    if x_state & 0x10 != 0 {
+        state |= ModifierState_NUMLOCK;
+    }
+
+    state
+}
@@ -0,0 +1,133 @@
+use std::num::NonZeroU32;
+
+use x11rb::{
+    protocol::xproto::{
+        ConfigureNotifyEvent, ConfigureWindowAux, ConnectionExt as _, ExposeEvent, EXPOSE_EVENT,
+    },
+    xcb_ffi::XCBConnection,
+};
+use xim::x11rb::HasConnection;
+
+use crate::pe_window::create_popup_window;
+use crate::style::{resize_window, set_source_color, Style};
+
+/// Badge of current input mode
+pub struct IndicatorWindow {
+    window: NonZeroU32,
+    surface: cairo::XCBSurface,
+    cr: cairo::Context,
+    layout: pango::Layout,
+    size: (u16, u16),
+}
+
+impl IndicatorWindow {
+    pub fn new(
+        conn: &XCBConnection,
+        style: &Style,
+        pos: (i16, i16),
+        screen_num: usize,
+    ) -> Result<Self, xim::ServerError> {
+        let size = (1, 1);
+        let (window, surface) = create_popup_window(conn, screen_num, pos, size)?;
+
+        let cr = cairo::Context::new(&surface);
+        let layout = style.create_layout(&cr);
+
+        Ok(Self {
+            window,
+            surface,
+            cr,
+            layout,
+            size,
+        })
+    }
+
+    pub fn clean<C: HasConnection>(self, c: C) -> Result<(), xim::ServerError> {
+        let conn = c.conn();
+        self.surface.finish();
+        conn.destroy_window(self.window.get())?.ignore_error();
+        conn.flush()?;
+
+        Ok(())
+    }
+
+    pub fn window(&self) -> NonZeroU32 {
+        self.window
+    }
+
+    pub fn size(&self) -> (u16, u16) {
+        self.size
+    }
+
+    fn redraw(&mut self, style: &Style) {
+        let inset = style.inset();
+
+        style.draw_frame(&self.cr, self.size);
+
+        set_source_color(&self.cr, style.theme().foreground);
+        self.cr.move_to(inset, inset);
+        pangocairo::functions::show_layout(&self.cr, &self.layout);
+
+        self.surface.flush();
+    }
+
+    pub fn expose(&mut self, style: &Style) {
+        self.redraw(style);
+    }
+
+    pub fn configure_notify(&mut self, e: ConfigureNotifyEvent, style: &Style) {
+        self.size = (e.width, e.height);
+        self.surface.set_size(e.width as _, e.height as _).unwrap();
+        self.redraw(style);
+    }
+
+    /// Show input mode, window is resized to fit it
+    pub fn set_mode(
+        &mut self,
+        conn: &XCBConnection,
+        style: &Style,
+        hangul: bool,
+    ) -> Result<(), xim::ServerError> {
+        self.layout.set_text(if hangul { "한" } else { "A" });
+
+        let size = style.window_size(self.layout.get_pixel_size());
+        resize_window(conn, self.window, &self.surface, &mut self.size, size)?;
+
+        self.refresh(conn)
+    }
+
+    pub fn move_to(
+        &self,
+        conn: &XCBConnection,
+        (x, y): (i16, i16),
+    ) -> Result<(), xim::ServerError> {
+        conn.configure_window(
+            self.window.get(),
+            &ConfigureWindowAux::new().x(x as i32).y(y as i32),
+        )?;
+        conn.flush()?;
+
+        Ok(())
+    }
+
+    fn refresh(&self, conn: &XCBConnection) -> Result<(), xim::ServerError> {
+        conn.send_event(
+            false,
+            self.window.get(),
+            0u32,
+            ExposeEvent {
+                response_type: EXPOSE_EVENT,
+                window: self.window.get(),
+                width: 0,
+                height: 0,
+                x: 0,
+                y: 0,
+                sequence: 0,
+                count: 0,
+            },
+        )?;
+        conn.flush()?;
+
+        Ok(())
+    }
+}
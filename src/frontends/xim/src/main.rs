@@ -1,11 +1,18 @@
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::Duration;
+
 use x11rb::{
     connection::Connection,
     protocol::{ErrorKind, Event},
 };
 use xim::{x11rb::HasConnection, ServerError, XimConnections};
 
+mod candidate_window;
+mod feedback;
 mod handler;
+mod indicator_window;
 mod pe_window;
+mod style;
 
 fn main_inner() -> Result<(), ServerError> {
     let config = kime_engine_cffi::Config::load();
@@ -13,10 +20,21 @@ fn main_inner() -> Result<(), ServerError> {
     let (conn, screen_num) = x11rb::xcb_ffi::XCBConnection::connect(None)?;
     let mut server = xim::x11rb::X11rbServer::init(conn, screen_num, "kime", xim::ALL_LOCALES)?;
     let mut connections = XimConnections::new();
-    let mut handler = self::handler::KimeHandler::new(screen_num, config);
+    let mut handler = self::handler::KimeHandler::new(server.conn(), screen_num, config);
 
     loop {
-        let e = server.conn().wait_for_event()?;
+        let e = match handler.indicator_timeout() {
+            Some(timeout) => match server.conn().poll_for_event()? {
+                Some(e) => e,
+                None => {
+                    server.conn().flush()?;
+                    wait_readable(server.conn().as_raw_fd(), timeout);
+                    handler.handle_indicator_timeout(server.conn())?;
+                    continue;
+                }
+            },
+            None => server.conn().wait_for_event()?,
+        };
         if !server.filter_event(&e, &mut connections, &mut handler)? {
             match e {
                 Event::Expose(e) => {
@@ -42,6 +60,22 @@ fn main_inner() -> Result<(), ServerError> {
     }
 }
 
+/// Wait until fd is readable or timeout is expired
+fn wait_readable(fd: RawFd, timeout: Duration) {
+    let mut pollfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    // Round up so deadline is passed when poll is timed out
+    let timeout = timeout.as_millis().saturating_add(1).min(i32::MAX as u128) as i32;
+
+    // Error like EINTR is same as early wake up, caller checks deadline again
+    unsafe {
+        libc::poll(&mut pollfd, 1, timeout);
+    }
+}
+
 fn main() {
     kime_version::cli_boilerplate!();
 
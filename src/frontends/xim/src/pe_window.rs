@@ -1,4 +1,7 @@
 use std::num::NonZeroU32;
+use std::ops::Range;
+
+use kime_engine_cffi::InputEngine;
 
 use x11rb::{
     connection::Connection,
@@ -15,109 +18,46 @@ use x11rb::{
 };
 use xim::x11rb::HasConnection;
 
+use crate::feedback::{preedit_feedbacks, XIM_HIGHLIGHT, XIM_REVERSE, XIM_UNDERLINE};
+use crate::style::{pango_color, resize_window, set_source_color, Style};
+
 pub struct PeWindow {
     preedit_window: NonZeroU32,
     preedit: String,
+    cursor: usize,
+    feedbacks: Vec<(Range<usize>, u32)>,
     surface: cairo::XCBSurface,
     cr: cairo::Context,
-    text_pos: (f64, f64),
+    layout: pango::Layout,
+    size: (u16, u16),
 }
 
 impl PeWindow {
     pub fn new(
         conn: &XCBConnection,
-        (font, font_size): (&str, f64),
+        style: &Style,
         app_win: Option<NonZeroU32>,
         spot_location: xim::Point,
         screen_num: usize,
     ) -> Result<Self, xim::ServerError> {
-        let size = (font_size * 1.7) as u16;
-        let size = (size, size);
-        let preedit_window = conn.generate_id()?;
-        let colormap = conn.generate_id()?;
-        let (depth, visual_id) = choose_visual(conn, screen_num)?;
-
+        // Resized to fit preedit later
+        let size = (1, 1);
         let screen = &conn.setup().roots[screen_num];
         let pos = find_position(conn, screen.root, app_win, spot_location)?;
-
-        conn.create_colormap(ColormapAlloc::NONE, colormap, screen.root, visual_id)?
-            .check()?;
-
-        conn.create_window(
-            depth,
-            preedit_window,
-            screen.root,
-            pos.0,
-            pos.1,
-            size.0,
-            size.1,
-            0,
-            WindowClass::INPUT_OUTPUT,
-            visual_id,
-            &CreateWindowAux::default()
-                .background_pixel(x11rb::NONE)
-                .border_pixel(x11rb::NONE)
-                .override_redirect(1u32)
-                .event_mask(EventMask::EXPOSURE | EventMask::STRUCTURE_NOTIFY)
-                .colormap(colormap),
-        )?
-        .check()?;
-
-        conn.free_colormap(colormap)?;
-
-        let window_type = conn
-            .intern_atom(false, b"_NET_WM_WINDOW_TYPE\0")?
-            .reply()?
-            .atom;
-        let popup = conn
-            .intern_atom(false, b"_NET_WM_WINDOW_TYPE_DOCK\0")?
-            .reply()?
-            .atom;
-
-        conn.change_property32(
-            PropMode::REPLACE,
-            preedit_window,
-            window_type,
-            AtomEnum::ATOM,
-            &[popup],
-        )?;
-
-        conn.change_property8(
-            PropMode::REPLACE,
-            preedit_window,
-            AtomEnum::WM_CLASS,
-            AtomEnum::STRING,
-            b"kime\0kime\0",
-        )?;
-
-        let mut visual = find_xcb_visualtype(conn, visual_id).unwrap();
-        let cairo_conn =
-            unsafe { cairo::XCBConnection::from_raw_none(conn.get_raw_xcb_connection() as _) };
-        let visual = unsafe { cairo::XCBVisualType::from_raw_none(&mut visual as *mut _ as _) };
-        let surface = cairo::XCBSurface::create(
-            &cairo_conn,
-            &cairo::XCBDrawable(preedit_window),
-            &visual,
-            size.0 as _,
-            size.1 as _,
-        )
-        .unwrap();
-
-        conn.map_window(preedit_window)?.check()?;
-
-        conn.flush()?;
+        let (preedit_window, surface) = create_popup_window(conn, screen_num, pos, size)?;
 
         let cr = cairo::Context::new(&surface);
-
-        cr.select_font_face(&font, cairo::FontSlant::Normal, cairo::FontWeight::Normal);
-        cr.set_font_size(font_size);
+        let layout = style.create_layout(&cr);
 
         Ok(Self {
             surface,
             cr,
-            preedit_window: NonZeroU32::new(preedit_window).unwrap(),
+            layout,
+            preedit_window,
             preedit: String::with_capacity(10),
-            text_pos: (font_size * 0.36, font_size * 1.10),
+            cursor: 0,
+            feedbacks: Vec::new(),
+            size,
         })
     }
 
@@ -135,27 +75,40 @@ impl PeWindow {
         self.preedit_window
     }
 
-    fn redraw(&mut self) {
+    fn redraw(&mut self, style: &Style) {
         log::trace!("Redraw: {}", self.preedit);
-        self.cr.set_source_rgb(1.0, 1.0, 1.0);
-        self.cr.paint();
+        style.draw_frame(&self.cr, self.size);
 
         if !self.preedit.is_empty() {
-            self.cr.set_source_rgb(0.0, 0.0, 0.0);
-            self.cr.move_to(self.text_pos.0, self.text_pos.1);
-            self.cr.show_text(&self.preedit);
+            let inset = style.inset();
+
+            self.cr.move_to(inset, inset);
+            set_source_color(&self.cr, style.theme().foreground);
+            pangocairo::functions::show_layout(&self.cr, &self.layout);
+
+            if self.cursor < self.preedit.len() {
+                // Draw a caret where the cursor sits inside the preedit
+                let (caret, _) = self.layout.get_cursor_pos(self.cursor as i32);
+                let x = inset + (caret.x / pango::SCALE) as f64 + 0.5;
+                let y = inset + (caret.y / pango::SCALE) as f64;
+                self.cr.set_line_width(1.0);
+                self.cr.move_to(x, y);
+                self.cr.line_to(x, y + (caret.height / pango::SCALE) as f64);
+                self.cr.stroke();
+            }
         }
 
         self.surface.flush();
     }
 
-    pub fn expose(&mut self) {
-        self.redraw();
+    pub fn expose(&mut self, style: &Style) {
+        self.redraw(style);
     }
 
-    pub fn configure_notify(&mut self, e: ConfigureNotifyEvent) {
+    pub fn configure_notify(&mut self, e: ConfigureNotifyEvent, style: &Style) {
+        self.size = (e.width, e.height);
         self.surface.set_size(e.width as _, e.height as _).unwrap();
-        self.redraw();
+        self.redraw(style);
     }
 
     pub fn refresh(&self, conn: &XCBConnection) -> Result<(), xim::ServerError> {
@@ -179,12 +132,155 @@ impl PeWindow {
         Ok(())
     }
 
-    pub fn set_preedit(&mut self, s: &str) {
+    /// Layout preedit with feedbacks and resize window to fit it
+    pub fn set_preedit(
+        &mut self,
+        conn: &XCBConnection,
+        style: &Style,
+        engine: &InputEngine,
+    ) -> Result<(), xim::ServerError> {
         self.preedit.clear();
-        self.preedit.push_str(s);
+        self.preedit.push_str(engine.preedit_str());
+        self.cursor = engine.preedit_cursor();
+        preedit_feedbacks(
+            self.preedit.len(),
+            engine.preedit_segments(),
+            &mut self.feedbacks,
+        );
+
+        let theme = style.theme();
+        let attrs = pango::AttrList::new();
+
+        for (range, feedback) in self.feedbacks.iter() {
+            let (fg, bg) = if feedback & XIM_REVERSE != 0 {
+                (Some(theme.background), Some(theme.foreground))
+            } else if feedback & XIM_HIGHLIGHT != 0 {
+                (None, Some(theme.highlight))
+            } else {
+                (None, None)
+            };
+
+            let underline = if feedback & XIM_UNDERLINE != 0 {
+                pango::Attribute::new_underline(pango::Underline::Single)
+            } else {
+                None
+            };
+
+            let fg = fg.and_then(|c| {
+                let (r, g, b) = pango_color(c);
+                pango::Attribute::new_foreground(r, g, b)
+            });
+            let bg = bg.and_then(|c| {
+                let (r, g, b) = pango_color(c);
+                pango::Attribute::new_background(r, g, b)
+            });
+
+            for mut attr in fg.into_iter().chain(bg).chain(underline) {
+                attr.set_start_index(range.start as u32);
+                attr.set_end_index(range.end as u32);
+                attrs.insert(attr);
+            }
+        }
+
+        self.layout.set_text(&self.preedit);
+        self.layout.set_attributes(Some(&attrs));
+
+        let size = style.window_size(self.layout.get_pixel_size());
+        resize_window(
+            conn,
+            self.preedit_window,
+            &self.surface,
+            &mut self.size,
+            size,
+        )?;
+
+        self.refresh(conn)
     }
 }
 
+/// Create override-redirect window placed at `pos` and cairo surface which draws on it
+pub fn create_popup_window(
+    conn: &XCBConnection,
+    screen_num: usize,
+    pos: (i16, i16),
+    size: (u16, u16),
+) -> Result<(NonZeroU32, cairo::XCBSurface), xim::ServerError> {
+    let preedit_window = conn.generate_id()?;
+    let colormap = conn.generate_id()?;
+    let (depth, visual_id) = choose_visual(conn, screen_num)?;
+
+    let screen = &conn.setup().roots[screen_num];
+
+    conn.create_colormap(ColormapAlloc::NONE, colormap, screen.root, visual_id)?
+        .check()?;
+
+    conn.create_window(
+        depth,
+        preedit_window,
+        screen.root,
+        pos.0,
+        pos.1,
+        size.0,
+        size.1,
+        0,
+        WindowClass::INPUT_OUTPUT,
+        visual_id,
+        &CreateWindowAux::default()
+            .background_pixel(x11rb::NONE)
+            .border_pixel(x11rb::NONE)
+            .override_redirect(1u32)
+            .event_mask(EventMask::EXPOSURE | EventMask::STRUCTURE_NOTIFY)
+            .colormap(colormap),
+    )?
+    .check()?;
+
+    conn.free_colormap(colormap)?;
+
+    let window_type = conn
+        .intern_atom(false, b"_NET_WM_WINDOW_TYPE\0")?
+        .reply()?
+        .atom;
+    let popup = conn
+        .intern_atom(false, b"_NET_WM_WINDOW_TYPE_DOCK\0")?
+        .reply()?
+        .atom;
+
+    conn.change_property32(
+        PropMode::REPLACE,
+        preedit_window,
+        window_type,
+        AtomEnum::ATOM,
+        &[popup],
+    )?;
+
+    conn.change_property8(
+        PropMode::REPLACE,
+        preedit_window,
+        AtomEnum::WM_CLASS,
+        AtomEnum::STRING,
+        b"kime\0kime\0",
+    )?;
+
+    let mut visual = find_xcb_visualtype(conn, visual_id).unwrap();
+    let cairo_conn =
+        unsafe { cairo::XCBConnection::from_raw_none(conn.get_raw_xcb_connection() as _) };
+    let visual = unsafe { cairo::XCBVisualType::from_raw_none(&mut visual as *mut _ as _) };
+    let surface = cairo::XCBSurface::create(
+        &cairo_conn,
+        &cairo::XCBDrawable(preedit_window),
+        &visual,
+        size.0 as _,
+        size.1 as _,
+    )
+    .unwrap();
+
+    conn.map_window(preedit_window)?.check()?;
+
+    conn.flush()?;
+
+    Ok((NonZeroU32::new(preedit_window).unwrap(), surface))
+}
+
 /// Choose a visual to use. This function tries to find a depth=32 visual and falls back to the
 /// screen's default visual.
 fn choose_visual(conn: &impl Connection, screen_num: usize) -> Result<(u8, Visualid), ReplyError> {
@@ -268,7 +364,7 @@ fn find_xcb_visualtype(conn: &impl Connection, visual_id: u32) -> Option<xcb_vis
     None
 }
 
-fn find_position(
+pub fn find_position(
     conn: &impl Connection,
     root: u32,
     app_win: Option<NonZeroU32>,
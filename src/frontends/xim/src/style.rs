@@ -0,0 +1,162 @@
+use std::num::NonZeroU32;
+
+use kime_engine_cffi::{Color, Config, Theme};
+
+use x11rb::{
+    connection::Connection,
+    protocol::xproto::{AtomEnum, ConfigureWindowAux, ConnectionExt as _},
+    xcb_ffi::XCBConnection,
+};
+
+/// DPI which X assumes when `Xft.dpi` is not set
+const DEFAULT_DPI: f64 = 96.0;
+
+/// Font, theme and DPI scale shared by windows of server
+pub struct Style {
+    font: pango::FontDescription,
+    /// Font size in device pixels
+    font_size: f64,
+    theme: Theme,
+    scale: f64,
+}
+
+impl Style {
+    pub fn new(conn: &XCBConnection, screen_num: usize, config: &Config) -> Self {
+        let (font, font_size) = config.xim_font();
+        let scale = xft_dpi(conn, screen_num).unwrap_or(DEFAULT_DPI) / DEFAULT_DPI;
+        let font_size = font_size * scale;
+
+        log::info!("Font: {} {}px, scale: {}", font, font_size, scale);
+
+        // pango falls back to other fonts by fontconfig when glyph is missing
+        let mut desc = pango::FontDescription::from_string(font);
+        desc.set_absolute_size(font_size * pango::SCALE as f64);
+
+        Self {
+            font: desc,
+            font_size,
+            theme: config.theme(),
+            scale,
+        }
+    }
+
+    pub fn theme(&self) -> &Theme {
+        &self.theme
+    }
+
+    /// Convert size at 96 DPI to device pixels
+    fn scaled(&self, size: f64) -> f64 {
+        (size * self.scale).round()
+    }
+
+    /// Distance from window edge to text
+    pub fn inset(&self) -> f64 {
+        self.scaled(self.theme.border_width) + self.scaled(self.theme.padding)
+    }
+
+    /// Rough height of preedit window, candidate window is placed below it
+    pub fn preedit_height(&self) -> f64 {
+        (self.font_size * 1.3 + self.inset() * 2.0).ceil()
+    }
+
+    pub fn create_layout(&self, cr: &cairo::Context) -> pango::Layout {
+        let layout = pangocairo::functions::create_layout(cr).expect("Can't create pango layout");
+        layout.set_font_description(Some(&self.font));
+        layout
+    }
+
+    /// Fill window with background and draw border
+    pub fn draw_frame(&self, cr: &cairo::Context, (width, height): (u16, u16)) {
+        let (width, height) = (width as f64, height as f64);
+
+        set_source_color(cr, self.theme.background);
+        cr.paint();
+
+        let border = self.scaled(self.theme.border_width);
+        if border > 0.0 {
+            set_source_color(cr, self.theme.border);
+            cr.set_line_width(border);
+            cr.rectangle(border / 2.0, border / 2.0, width - border, height - border);
+            cr.stroke();
+        }
+    }
+
+    /// Size of window which fits text of given size
+    pub fn window_size(&self, (width, height): (i32, i32)) -> (u16, u16) {
+        let inset = self.inset() * 2.0;
+
+        (
+            (width as f64 + inset).ceil() as u16,
+            (height as f64 + inset).ceil() as u16,
+        )
+    }
+}
+
+pub fn set_source_color(cr: &cairo::Context, color: Color) {
+    cr.set_source_rgb(
+        color.r as f64 / 255.0,
+        color.g as f64 / 255.0,
+        color.b as f64 / 255.0,
+    );
+}
+
+/// Color of pango attribute
+pub fn pango_color(color: Color) -> (u16, u16, u16) {
+    // 0xff -> 0xffff
+    (
+        color.r as u16 * 257,
+        color.g as u16 * 257,
+        color.b as u16 * 257,
+    )
+}
+
+/// Resize window and its surface when size is changed
+pub fn resize_window(
+    conn: &XCBConnection,
+    window: NonZeroU32,
+    surface: &cairo::XCBSurface,
+    size: &mut (u16, u16),
+    new_size: (u16, u16),
+) -> Result<(), xim::ServerError> {
+    if *size != new_size {
+        conn.configure_window(
+            window.get(),
+            &ConfigureWindowAux::new()
+                .width(new_size.0 as u32)
+                .height(new_size.1 as u32),
+        )?;
+        *size = new_size;
+        surface.set_size(new_size.0 as _, new_size.1 as _).unwrap();
+    }
+
+    Ok(())
+}
+
+/// Read `Xft.dpi` from `RESOURCE_MANAGER` property of root window which is set by desktop
+fn xft_dpi(conn: &XCBConnection, screen_num: usize) -> Option<f64> {
+    let root = conn.setup().roots[screen_num].root;
+    let reply = conn
+        .get_property(
+            false,
+            root,
+            AtomEnum::RESOURCE_MANAGER,
+            AtomEnum::STRING,
+            0,
+            u32::MAX,
+        )
+        .ok()?
+        .reply()
+        .ok()?;
+
+    String::from_utf8_lossy(&reply.value)
+        .lines()
+        .find_map(|line| {
+            let mut kv = line.splitn(2, ':');
+            if kv.next()?.trim() == "Xft.dpi" {
+                kv.next()?.trim().parse().ok()
+            } else {
+                None
+            }
+        })
+        .filter(|&dpi: &f64| dpi > 0.0)
+}